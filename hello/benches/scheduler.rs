@@ -0,0 +1,126 @@
+// Compares the work-stealing ThreadPool with the original design, where
+// every worker blocks on one Arc<Mutex<mpsc::Receiver>>. Results go to
+// stderr:
+//
+//   cargo bench --bench scheduler
+use hello::ThreadPool;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const THREADS: usize = 4;
+const JOBS: usize = 100_000;
+const ROUNDS: usize = 5;
+
+fn main() {
+    for (name, run) in [
+        ("mutex receiver", run_legacy as fn(usize, fn()) -> Duration),
+        ("work stealing", run_stealing),
+    ] {
+        for (workload, job) in [("empty jobs", empty as fn()), ("1k spin jobs", spin)] {
+            let best = (0..ROUNDS).map(|_| run(JOBS, job)).min().unwrap();
+            eprintln!(
+                "{:>15} | {:>12} | {:>8.1?} | {:>6.0} ns/job",
+                name,
+                workload,
+                best,
+                best.as_nanos() as f64 / JOBS as f64
+            );
+        }
+    }
+}
+
+fn empty() {}
+
+fn spin() {
+    let mut x = 0u64;
+    for i in 0..1000 {
+        x = std::hint::black_box(x.wrapping_add(i));
+    }
+}
+
+fn run_stealing(jobs: usize, job: fn()) -> Duration {
+    let pool = ThreadPool::new(THREADS);
+    time(jobs, |f| pool.execute(f), job)
+}
+
+fn run_legacy(jobs: usize, job: fn()) -> Duration {
+    let pool = LegacyPool::new(THREADS);
+    time(jobs, |f| pool.execute(f), job)
+}
+
+// Submits `jobs` jobs and waits until the last one has run.
+fn time(jobs: usize, submit: impl Fn(Box<dyn FnOnce() + Send>), job: fn()) -> Duration {
+    let done = Arc::new(AtomicUsize::new(0));
+    let (finished, wait) = mpsc::channel();
+    let finished = Arc::new(Mutex::new(finished));
+
+    let start = Instant::now();
+    for _ in 0..jobs {
+        let done = Arc::clone(&done);
+        let finished = Arc::clone(&finished);
+        submit(Box::new(move || {
+            job();
+            if done.fetch_add(1, Ordering::SeqCst) + 1 == jobs {
+                finished.lock().unwrap().send(()).unwrap();
+            }
+        }));
+    }
+    wait.recv().unwrap();
+    start.elapsed()
+}
+
+// The ThreadPool as it was before the scheduler rewrite.
+struct LegacyPool {
+    workers: Vec<Option<thread::JoinHandle<()>>>,
+    sender: mpsc::Sender<Message>,
+}
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+enum Message {
+    NewJob(Job),
+    Terminate,
+}
+
+impl LegacyPool {
+    fn new(size: usize) -> LegacyPool {
+        let (sender, receiver) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..size)
+            .map(|_| {
+                let receiver: Arc<Mutex<mpsc::Receiver<Message>>> = Arc::clone(&receiver);
+                Some(thread::spawn(move || loop {
+                    let message = receiver.lock().unwrap().recv().unwrap();
+                    match message {
+                        Message::NewJob(job) => job(),
+                        Message::Terminate => break,
+                    }
+                }))
+            })
+            .collect();
+        LegacyPool { workers, sender }
+    }
+
+    fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.sender.send(Message::NewJob(Box::new(f))).unwrap();
+    }
+}
+
+impl Drop for LegacyPool {
+    fn drop(&mut self) {
+        for _ in &self.workers {
+            self.sender.send(Message::Terminate).unwrap();
+        }
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.take() {
+                thread.join().unwrap();
+            }
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use crate::http_date;
+use crate::log;
+use crate::log::Level;
+use crate::request::Request;
+use crate::response::Response;
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// One line per request, in the Common or Combined Log Format with the time
+// taken to serve the request appended in microseconds (Apache's %D):
+//
+//     127.0.0.1 - - [10/Oct/2024:13:55:36 +0000] "GET /a.png HTTP/1.1" 200 2326 "-" "curl/8.5.0" 1843
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Common,
+    // Common plus the Referer and User-Agent headers.
+    Combined,
+}
+
+pub struct AccessLog {
+    format: Format,
+    output: Mutex<Output>,
+}
+
+enum Output {
+    Stdout,
+    File(LogFile),
+}
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    // Rotate once the file would grow past this many bytes.
+    max_size: Option<u64>,
+    // How many rotated files to keep: path.1 is the newest.
+    keep: usize,
+}
+
+// What we need to remember about a request to log it once the response is
+// out; the request itself is gone by then.
+pub(crate) struct Entry {
+    peer: Option<IpAddr>,
+    time: SystemTime,
+    start: Instant,
+    request_line: String,
+    referer: Option<String>,
+    user_agent: Option<String>,
+}
+
+impl AccessLog {
+    pub fn stdout(format: Format) -> AccessLog {
+        AccessLog {
+            format,
+            output: Mutex::new(Output::Stdout),
+        }
+    }
+
+    // Appends to the file, creating it if needed. Without a max_size it
+    // grows forever.
+    pub fn file(path: impl Into<PathBuf>, format: Format) -> io::Result<AccessLog> {
+        let path = path.into();
+        let file = open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(AccessLog {
+            format,
+            output: Mutex::new(Output::File(LogFile {
+                path,
+                file,
+                size,
+                max_size: None,
+                keep: 5,
+            })),
+        })
+    }
+
+    // Rotates the file once it reaches `bytes`: path becomes path.1, path.1
+    // becomes path.2 and so on, and the oldest is deleted. Has no effect on
+    // a stdout log.
+    pub fn max_size(self, bytes: u64) -> AccessLog {
+        if let Output::File(log) = &mut *lock(&self.output) {
+            log.max_size = Some(bytes.max(1));
+        }
+        self
+    }
+
+    // How many rotated files to keep. Five by default.
+    pub fn keep(self, files: usize) -> AccessLog {
+        if let Output::File(log) = &mut *lock(&self.output) {
+            log.keep = files;
+        }
+        self
+    }
+
+    pub(crate) fn begin(request: &Request, peer: Option<IpAddr>) -> Entry {
+        let mut request_line = format!("{} ", request.method);
+        escape(&request.path, &mut request_line);
+        if let Some(query) = &request.query {
+            request_line.push('?');
+            escape(query, &mut request_line);
+        }
+        let _ = write!(request_line, " {}", request.version);
+        Entry {
+            peer,
+            time: SystemTime::now(),
+            start: Instant::now(),
+            request_line,
+            referer: request.header("Referer").map(str::to_string),
+            user_agent: request.header("User-Agent").map(str::to_string),
+        }
+    }
+
+    // Logs a response sent without a request to go with it: a malformed or
+    // timed-out request, or a connection turned away. Like Apache, the
+    // request line is "-". `sent` is as for finish.
+    pub(crate) fn unparsed(&self, peer: Option<IpAddr>, response: &Response, sent: usize) {
+        let entry = Entry {
+            peer,
+            time: SystemTime::now(),
+            start: Instant::now(),
+            request_line: "-".to_string(),
+            referer: None,
+            user_agent: None,
+        };
+        self.finish(entry, response, sent);
+    }
+
+    // `sent` is the number of body bytes written, so 0 for HEAD.
+    pub(crate) fn finish(&self, entry: Entry, response: &Response, sent: usize) {
+        let line = self.format_line(&entry, response.status_code(), sent, entry.start.elapsed());
+        if let Err(e) = lock(&self.output).write(line.as_bytes()) {
+            log!(Level::Error, "Failed to write access log: {}", e);
+        }
+    }
+
+    fn format_line(&self, entry: &Entry, status: u16, sent: usize, duration: Duration) -> String {
+        let mut line = String::with_capacity(160);
+        match entry.peer {
+            Some(ip) => {
+                let _ = write!(line, "{}", ip);
+            }
+            None => line.push('-'),
+        }
+        let _ = write!(
+            line,
+            " - - [{}] \"{}\" {} ",
+            timestamp(entry.time),
+            entry.request_line,
+            status
+        );
+        if sent == 0 {
+            line.push('-');
+        } else {
+            let _ = write!(line, "{}", sent);
+        }
+        if self.format == Format::Combined {
+            for header in [&entry.referer, &entry.user_agent] {
+                line.push_str(" \"");
+                match header {
+                    Some(value) => escape(value, &mut line),
+                    None => line.push('-'),
+                }
+                line.push('"');
+            }
+        }
+        let _ = writeln!(line, " {}", duration.as_micros());
+        line
+    }
+}
+
+impl Output {
+    fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        match self {
+            Output::Stdout => io::stdout().lock().write_all(line),
+            Output::File(log) => {
+                let len = line.len() as u64;
+                if log
+                    .max_size
+                    .is_some_and(|max| log.size > 0 && log.size + len > max)
+                {
+                    log.rotate()?;
+                }
+                log.file.write_all(line)?;
+                log.size += len;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl LogFile {
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(rotated(self.keep));
+            for n in (1..self.keep).rev() {
+                if let Err(e) = fs::rename(rotated(n), rotated(n + 1)) {
+                    if e.kind() != io::ErrorKind::NotFound {
+                        return Err(e);
+                    }
+                }
+            }
+            fs::rename(&self.path, rotated(1))?;
+        }
+        self.file = open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// "10/Oct/2024:13:55:36 +0000". We always log in UTC.
+fn timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs();
+    let (year, month, day) = http_date::civil_from_days((secs / 86400) as i64);
+    let seconds_of_day = secs % 86400;
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day,
+        http_date::MONTHS[(month - 1) as usize],
+        year,
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60
+    )
+}
+
+// Quotes and control characters would let a client forge log lines, so
+// they are written as escapes, like Apache does.
+fn escape(value: &str, out: &mut String) {
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\x{:02x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::Request;
+    use crate::response::Status;
+
+    #[test]
+    fn formats_and_rotates() {
+        let mut raw: &[u8] = b"GET /a%20b?q=1 HTTP/1.1\r\nUser-Agent: curl \"x\"\r\n\r\n";
+        let request = Request::read_from(&mut raw).unwrap();
+        let mut entry = AccessLog::begin(&request, Some(IpAddr::from([10, 0, 0, 1])));
+        entry.time = UNIX_EPOCH + Duration::from_secs(1_728_568_536);
+
+        let combined = AccessLog::stdout(Format::Combined).format_line(
+            &entry,
+            200,
+            2326,
+            Duration::from_micros(1843),
+        );
+        assert_eq!(
+            "10.0.0.1 - - [10/Oct/2024:13:55:36 +0000] \"GET /a b?q=1 HTTP/1.1\" 200 2326 \
+             \"-\" \"curl \\\"x\\\"\" 1843\n",
+            combined
+        );
+        let common = AccessLog::stdout(Format::Common).format_line(&entry, 304, 0, Duration::ZERO);
+        assert!(common.ends_with("\"GET /a b?q=1 HTTP/1.1\" 304 - 0\n"));
+
+        let dir = std::env::temp_dir().join(format!("hello-access-log-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("access.log");
+        let log = AccessLog::file(&path, Format::Common)
+            .unwrap()
+            .max_size(common.len() as u64 * 2)
+            .keep(1);
+        let response = Response::new(Status::NotModified, "");
+        for _ in 0..5 {
+            let mut raw: &[u8] = b"GET /a%20b?q=1 HTTP/1.1\r\n\r\n";
+            let request = Request::read_from(&mut raw).unwrap();
+            log.finish(AccessLog::begin(&request, None), &response, 0);
+        }
+        let lines = |path: &Path| fs::read_to_string(path).unwrap().lines().count();
+        assert_eq!(1, lines(&path));
+        assert_eq!(2, lines(&dir.join("access.log.1")));
+        assert!(!dir.join("access.log.2").exists());
+
+        // Without a request, the request line is "-", and so is the size
+        // when the response didn't make it out.
+        let log = AccessLog::file(dir.join("unparsed.log"), Format::Combined).unwrap();
+        let response = Response::new(Status::ServiceUnavailable, "Service Unavailable\n");
+        log.unparsed(Some(IpAddr::from([10, 0, 0, 2])), &response, 20);
+        log.unparsed(None, &response, 0);
+        let logged = fs::read_to_string(dir.join("unparsed.log")).unwrap();
+        let lines: Vec<_> = logged.lines().collect();
+        assert!(lines[0].starts_with("10.0.0.2 - - ["));
+        assert!(lines[0].contains("] \"-\" 503 20 \"-\" \"-\" "));
+        assert!(lines[1].contains("] \"-\" 503 - \"-\" \"-\" "));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
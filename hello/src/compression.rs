@@ -0,0 +1,305 @@
+use crate::request::Request;
+use crate::response::{Response, Status};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use std::io::prelude::*;
+
+// Compresses response bodies with gzip or deflate when the client's
+// Accept-Encoding allows it. Small bodies aren't worth it, and formats that
+// are already compressed (images, video, archives) only get bigger, so only
+// bodies of at least min_size bytes with a listed Content-Type are touched.
+#[derive(Debug, Clone)]
+pub struct Compression {
+    min_size: usize,
+    // Media types, lowercase; "text/*" matches every text type.
+    mime_types: Vec<String>,
+    level: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Compression {
+    pub fn new() -> Compression {
+        Compression {
+            min_size: 1024,
+            mime_types: [
+                "text/*",
+                "application/json",
+                "application/javascript",
+                "application/xml",
+                "application/wasm",
+                "image/svg+xml",
+            ]
+            .iter()
+            .map(|mime| mime.to_string())
+            .collect(),
+            level: 6,
+        }
+    }
+
+    pub fn min_size(mut self, bytes: usize) -> Compression {
+        self.min_size = bytes;
+        self
+    }
+
+    // Replaces the list of compressible types.
+    pub fn mime_types(mut self, types: &[&str]) -> Compression {
+        self.mime_types = types.iter().map(|mime| mime.to_ascii_lowercase()).collect();
+        self
+    }
+
+    // 0 (fastest) to 9 (smallest). 6 by default.
+    pub fn level(mut self, level: u32) -> Compression {
+        self.level = level.min(9);
+        self
+    }
+
+    // Picks the encoding to use for this request, if any. Called before the
+    // handler runs, since the request is gone by the time we compress.
+    pub fn negotiate(&self, request: &Request) -> Option<Encoding> {
+        negotiate(request.header("Accept-Encoding")?)
+    }
+
+    // Compresses the body if it qualifies. Every response that qualifies
+    // gets `Vary: Accept-Encoding`, compressed or not, so caches don't hand
+    // a gzipped body to a client that can't read it or the other way round.
+    // A 304 has no body to measure but stands in for the cached one, so it
+    // is treated as that body would be. The handler tells us its size with
+    // a Content-Length, which is dropped again if the 200 would have been
+    // compressed, since the compressed length is unknown; without one, the
+    // 304 is left alone.
+    pub fn apply(&self, response: &mut Response, encoding: Option<Encoding>) {
+        let not_modified = response.status == Status::NotModified;
+        let size = if not_modified {
+            response
+                .headers
+                .get("Content-Length")
+                .and_then(|length| length.parse().ok())
+        } else {
+            Some(response.body.len())
+        };
+        if size.is_none_or(|size| size < self.min_size)
+            || response.headers.contains("Content-Encoding")
+            || response.headers.contains("Content-Range")
+            || !self.compressible(response.headers.get("Content-Type"))
+        {
+            return;
+        }
+        add_vary(response);
+        let Some(encoding) = encoding else {
+            return;
+        };
+        if not_modified {
+            response.headers.remove("Content-Length");
+            tag_etag(response, encoding);
+            return;
+        }
+        let compressed = match encoding {
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::new(self.level));
+                encoder
+                    .write_all(&response.body)
+                    .and_then(|()| encoder.finish())
+            }
+            Encoding::Deflate => {
+                let mut encoder =
+                    ZlibEncoder::new(Vec::new(), flate2::Compression::new(self.level));
+                encoder
+                    .write_all(&response.body)
+                    .and_then(|()| encoder.finish())
+            }
+        };
+        let Ok(compressed) = compressed else {
+            return;
+        };
+        // Kept even if it came out no smaller, so that whether a body is
+        // compressed, and so its ETag, depends only on its size and type,
+        // which is all a 304 has to go by.
+        response.body = compressed;
+        response
+            .headers
+            .append("Content-Encoding", encoding.as_str());
+        tag_etag(response, encoding);
+    }
+
+    fn compressible(&self, content_type: Option<&str>) -> bool {
+        let Some(content_type) = content_type else {
+            return false;
+        };
+        let mime = content_type
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_ascii_lowercase();
+        self.mime_types
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => mime.starts_with(prefix),
+                None => *pattern == mime,
+            })
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression::new()
+    }
+}
+
+impl Encoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+}
+
+// Takes the acceptable encoding with the highest q-value, preferring gzip
+// on a tie. "*" covers any encoding not listed, and q=0 rules one out.
+fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let mut gzip = None;
+    let mut deflate = None;
+    let mut any = None;
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        let mut q = 1.0;
+        for param in parts {
+            if let Some((key, value)) = param.split_once('=') {
+                if key.trim().eq_ignore_ascii_case("q") {
+                    q = value.trim().parse().unwrap_or(0.0);
+                }
+            }
+        }
+        match name.as_str() {
+            "gzip" | "x-gzip" => gzip = Some(q),
+            "deflate" => deflate = Some(q),
+            "*" => any = Some(q),
+            _ => {}
+        }
+    }
+    let gzip = gzip.or(any).unwrap_or(0.0);
+    let deflate = deflate.or(any).unwrap_or(0.0);
+    if gzip > 0.0 && gzip >= deflate {
+        Some(Encoding::Gzip)
+    } else if deflate > 0.0 {
+        Some(Encoding::Deflate)
+    } else {
+        None
+    }
+}
+
+// A strong ETag names exact bytes, so the compressed body needs its own.
+fn tag_etag(response: &mut Response, encoding: Encoding) {
+    if let Some(etag) = response.headers.get("ETag") {
+        if let Some(tag) = etag.strip_suffix('"') {
+            let etag = format!("{}-{}\"", tag, encoding.as_str());
+            response.headers.remove("ETag");
+            response.headers.append("ETag", &etag);
+        }
+    }
+}
+
+fn add_vary(response: &mut Response) {
+    let vary = response.headers.get("Vary").unwrap_or("");
+    let listed = vary.split(',').any(|name| {
+        let name = name.trim();
+        name == "*" || name.eq_ignore_ascii_case("Accept-Encoding")
+    });
+    if listed {
+        return;
+    }
+    let vary = if vary.trim().is_empty() {
+        "Accept-Encoding".to_string()
+    } else {
+        format!("{}, Accept-Encoding", vary)
+    };
+    response.headers.remove("Vary");
+    response.headers.append("Vary", &vary);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::Status;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn negotiates_encodings() {
+        assert_eq!(Some(Encoding::Gzip), negotiate("gzip, deflate, br"));
+        assert_eq!(Some(Encoding::Deflate), negotiate("gzip;q=0.5, deflate"));
+        assert_eq!(Some(Encoding::Deflate), negotiate("gzip;q=0, *"));
+        assert_eq!(Some(Encoding::Gzip), negotiate("*;q=0.1"));
+        assert_eq!(None, negotiate("br, identity"));
+        assert_eq!(None, negotiate("*;q=0"));
+    }
+
+    #[test]
+    fn compresses_only_what_qualifies() {
+        let compression = Compression::new().min_size(100);
+        let html = "<p>hello</p>".repeat(50);
+        let page = || {
+            Response::new(Status::Ok, html.clone())
+                .with_header("Content-Type", "text/html; charset=utf-8")
+                .with_header("ETag", "\"abc\"")
+        };
+
+        let mut response = page();
+        compression.apply(&mut response, Some(Encoding::Gzip));
+        assert_eq!(Some("gzip"), response.headers.get("Content-Encoding"));
+        assert_eq!(Some("Accept-Encoding"), response.headers.get("Vary"));
+        assert_eq!(Some("\"abc-gzip\""), response.headers.get("ETag"));
+        let mut decoded = String::new();
+        GzDecoder::new(&response.body[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(html, decoded);
+
+        // Not accepted: same body, but still varies.
+        let mut response = page().with_header("Vary", "Origin");
+        compression.apply(&mut response, None);
+        assert_eq!(html.as_bytes(), &response.body[..]);
+        assert_eq!(
+            Some("Origin, Accept-Encoding"),
+            response.headers.get("Vary")
+        );
+
+        let mut small = Response::new(Status::Ok, "tiny").with_header("Content-Type", "text/plain");
+        compression.apply(&mut small, Some(Encoding::Gzip));
+        let mut image =
+            Response::new(Status::Ok, vec![0; 1000]).with_header("Content-Type", "image/png");
+        compression.apply(&mut image, Some(Encoding::Gzip));
+        for response in [small, image] {
+            assert!(!response.headers.contains("Content-Encoding"));
+            assert!(!response.headers.contains("Vary"));
+        }
+
+        // A 304 carries what the 200 it stands for did: the page's was
+        // compressed, a small file's wasn't.
+        let not_modified = |size: usize| {
+            Response::new(Status::NotModified, "")
+                .with_header("Content-Type", "text/html; charset=utf-8")
+                .with_header("Content-Length", &size.to_string())
+                .with_header("ETag", "\"abc\"")
+        };
+        let mut response = not_modified(html.len());
+        compression.apply(&mut response, Some(Encoding::Gzip));
+        assert!(response.body.is_empty());
+        assert!(!response.headers.contains("Content-Encoding"));
+        assert!(!response.headers.contains("Content-Length"));
+        assert_eq!(Some("Accept-Encoding"), response.headers.get("Vary"));
+        assert_eq!(Some("\"abc-gzip\""), response.headers.get("ETag"));
+
+        let mut response = not_modified(50);
+        compression.apply(&mut response, Some(Encoding::Gzip));
+        assert_eq!(Some("50"), response.headers.get("Content-Length"));
+        assert!(!response.headers.contains("Vary"));
+        assert_eq!(Some("\"abc\""), response.headers.get("ETag"));
+    }
+}
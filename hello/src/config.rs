@@ -0,0 +1,649 @@
+use crate::access_log::Format;
+use crate::log::Level;
+use crate::request;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+// Read when no --config is given, if it exists.
+pub const DEFAULT_PATH: &str = "hello.toml";
+
+pub const USAGE: &str = "\
+Usage: hello [OPTIONS] [ROOT]
+
+Serves the hello pages, or the files under ROOT if given.
+
+Options:
+  --config PATH          Read settings from PATH (default: hello.toml, if present)
+  --address ADDR         IP address to bind to (default: 127.0.0.1)
+  --port PORT            Port to listen on (default: 7878)
+  --workers N            Worker threads kept running (default: 4)
+  --max-workers N        Upper limit on worker threads (default: 16)
+  --queue-capacity N     Connections waiting for a worker before we answer 503 (default: 64)
+  --root DIR             Serve the files under DIR
+  --async                Use the async server
+  --idle-timeout TIME    Close idle connections after TIME, e.g. 5s or 500ms (default: 5s)
+  --read-timeout TIME    Give up on a client that goes quiet mid-request (default: 10s)
+  --request-timeout TIME Give up on a request not fully received in TIME (default: 30s)
+  --write-timeout TIME   Give up on a client that stops reading the response (default: 10s)
+  --grace-period TIME    How long requests get to finish on shutdown (default: 10s)
+  --max-header-size SIZE Answer 431 to requests with larger headers (default: 16KB)
+  --max-connections-per-ip N
+                         Answer 429 to clients with N connections open (default: 0, no limit)
+  --log-level LEVEL      error, warn, info or debug (default: info)
+  --access-log PATH      Log every request to PATH, or to stdout if PATH is -
+  --access-log-format F  common or combined (default: combined)
+  --access-log-max-size SIZE
+                         Rotate the access log at SIZE, e.g. 10MB (default: never)
+  --access-log-keep N    Rotated access logs to keep (default: 5)
+  --compression BOOL     Gzip/deflate responses for clients that accept it (default: true)
+  --compression-min-size SIZE
+                         Leave smaller bodies uncompressed (default: 1KB)
+  --compression-level N  0 (fastest) to 9 (smallest) (default: 6)
+  --tls-cert PATH        Serve HTTPS with the PEM certificate chain at PATH
+  --tls-key PATH         The PEM private key for --tls-cert
+  --redirect-port PORT   Also listen for plain HTTP on PORT and redirect it to HTTPS
+  -h, --help             Print this help
+";
+
+// Every setting can come from the config file or the command line; flags
+// win. The file uses the same names, grouped into tables:
+//
+//     log_level = "debug"
+//
+//     [server]
+//     address = "0.0.0.0"
+//     port = 8080
+//     root = "public"
+//     async = true
+//
+//     [pool]
+//     workers = 8
+//     max_workers = 32
+//     queue_capacity = 128
+//
+//     [timeouts]
+//     idle = "5s"
+//     read = "10s"
+//     request = "30s"
+//     write = "10s"
+//     grace_period = "10s"
+//
+//     [limits]
+//     max_header_size = "16KB"
+//     max_connections_per_ip = 20
+//
+//     [access_log]
+//     path = "access.log"
+//     format = "common"
+//     max_size = "10MB"
+//     keep = 3
+//
+//     [compression]
+//     enabled = true
+//     min_size = "1KB"
+//     level = 6
+//
+//     [tls]
+//     cert = "cert.pem"
+//     key = "key.pem"
+//     redirect_port = 8080
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub address: IpAddr,
+    pub port: u16,
+    pub workers: usize,
+    pub max_workers: usize,
+    pub queue_capacity: usize,
+    pub root: Option<PathBuf>,
+    pub async_mode: bool,
+    pub idle_timeout: Duration,
+    pub read_timeout: Duration,
+    pub request_timeout: Duration,
+    pub write_timeout: Duration,
+    pub grace_period: Duration,
+    pub max_header_size: usize,
+    // None means no limit.
+    pub max_connections_per_ip: Option<usize>,
+    pub log_level: Level,
+    // "-" means stdout.
+    pub access_log: Option<PathBuf>,
+    pub access_log_format: Format,
+    pub access_log_max_size: Option<u64>,
+    pub access_log_keep: usize,
+    pub compression: bool,
+    pub compression_min_size: usize,
+    pub compression_level: u32,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub redirect_port: Option<u16>,
+}
+
+// Where a setting lives in the file, and the flag that overrides it.
+const SETTINGS: &[(&str, &str)] = &[
+    ("server.address", "--address"),
+    ("server.port", "--port"),
+    ("server.root", "--root"),
+    ("server.async", "--async"),
+    ("pool.workers", "--workers"),
+    ("pool.max_workers", "--max-workers"),
+    ("pool.queue_capacity", "--queue-capacity"),
+    ("timeouts.idle", "--idle-timeout"),
+    ("timeouts.read", "--read-timeout"),
+    ("timeouts.request", "--request-timeout"),
+    ("timeouts.write", "--write-timeout"),
+    ("timeouts.grace_period", "--grace-period"),
+    ("limits.max_header_size", "--max-header-size"),
+    ("limits.max_connections_per_ip", "--max-connections-per-ip"),
+    ("log_level", "--log-level"),
+    ("access_log.path", "--access-log"),
+    ("access_log.format", "--access-log-format"),
+    ("access_log.max_size", "--access-log-max-size"),
+    ("access_log.keep", "--access-log-keep"),
+    ("compression.enabled", "--compression"),
+    ("compression.min_size", "--compression-min-size"),
+    ("compression.level", "--compression-level"),
+    ("tls.cert", "--tls-cert"),
+    ("tls.key", "--tls-key"),
+    ("tls.redirect_port", "--redirect-port"),
+];
+
+#[derive(Debug)]
+pub enum ConfigError {
+    // --help was given; not really an error, but it stops startup.
+    Help,
+    Read(PathBuf, std::io::Error),
+    Syntax(PathBuf, toml::de::Error),
+    UnknownSetting(String),
+    MissingValue(String),
+    Invalid {
+        setting: String,
+        value: String,
+        expected: &'static str,
+    },
+    Inconsistent(String),
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            address: IpAddr::from([127, 0, 0, 1]),
+            port: 7878,
+            workers: 4,
+            max_workers: 16,
+            queue_capacity: 64,
+            root: None,
+            async_mode: false,
+            idle_timeout: Duration::from_secs(5),
+            read_timeout: Duration::from_secs(10),
+            request_timeout: Duration::from_secs(30),
+            write_timeout: Duration::from_secs(10),
+            grace_period: Duration::from_secs(10),
+            max_header_size: request::MAX_HEAD_SIZE,
+            max_connections_per_ip: None,
+            log_level: Level::Info,
+            access_log: None,
+            access_log_format: Format::Combined,
+            access_log_max_size: None,
+            access_log_keep: 5,
+            compression: true,
+            compression_min_size: 1024,
+            compression_level: 6,
+            tls_cert: None,
+            tls_key: None,
+            redirect_port: None,
+        }
+    }
+}
+
+impl Config {
+    // Builds the configuration from the defaults, then the config file, then
+    // the command-line flags (without the program name).
+    pub fn from_args<I>(args: I) -> Result<Config, ConfigError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let args: Vec<String> = args.into_iter().collect();
+        if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+            return Err(ConfigError::Help);
+        }
+
+        let mut config = Config::default();
+        match config_path(&args)? {
+            Some(path) => config.load(Path::new(path))?,
+            None if Path::new(DEFAULT_PATH).exists() => config.load(Path::new(DEFAULT_PATH))?,
+            None => {}
+        }
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--config" {
+                args.next();
+                continue;
+            }
+            if arg.starts_with("--config=") {
+                continue;
+            }
+            if arg == "--async" {
+                config.async_mode = true;
+                continue;
+            }
+            if !arg.starts_with('-') {
+                config.set("server.root", &arg, || "ROOT".to_string())?;
+                continue;
+            }
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+            let Some(&(key, _)) = SETTINGS.iter().find(|(_, f)| *f == flag) else {
+                return Err(ConfigError::UnknownSetting(flag));
+            };
+            let value = match inline.or_else(|| args.next()) {
+                Some(value) => value,
+                None => return Err(ConfigError::MissingValue(flag)),
+            };
+            config.set(key, &value, || flag.clone())?;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Applies the settings in a TOML file on top of the current values.
+    pub fn load(&mut self, path: &Path) -> Result<(), ConfigError> {
+        let text =
+            fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+        self.apply_toml(&text, path)
+    }
+
+    fn apply_toml(&mut self, text: &str, path: &Path) -> Result<(), ConfigError> {
+        let table: toml::Table = text
+            .parse()
+            .map_err(|e| ConfigError::Syntax(path.to_path_buf(), e))?;
+        let mut settings = Vec::new();
+        flatten("", &table, &mut settings);
+        for (key, value) in settings {
+            let value = match value {
+                toml::Value::String(s) => s.clone(),
+                toml::Value::Integer(n) => n.to_string(),
+                toml::Value::Float(n) => n.to_string(),
+                toml::Value::Boolean(b) => b.to_string(),
+                _ => {
+                    return Err(ConfigError::Invalid {
+                        setting: format!("{} in {}", key, path.display()),
+                        value: value.type_str().to_string(),
+                        expected: "a string, number or boolean",
+                    })
+                }
+            };
+            self.set(&key, &value, || format!("{} in {}", key, path.display()))?;
+        }
+        Ok(())
+    }
+
+    // `name` describes where the value came from, for error messages.
+    fn set(
+        &mut self,
+        key: &str,
+        value: &str,
+        name: impl Fn() -> String,
+    ) -> Result<(), ConfigError> {
+        let invalid = |expected| ConfigError::Invalid {
+            setting: name(),
+            value: value.to_string(),
+            expected,
+        };
+        match key {
+            "server.address" => {
+                self.address = value
+                    .parse()
+                    .map_err(|_| invalid("an IP address such as 127.0.0.1"))?
+            }
+            "server.port" => {
+                self.port = value
+                    .parse()
+                    .map_err(|_| invalid("a port number from 0 to 65535"))?
+            }
+            "server.root" => self.root = Some(PathBuf::from(value)),
+            "server.async" => {
+                self.async_mode = value.parse().map_err(|_| invalid("true or false"))?
+            }
+            "pool.workers" => {
+                self.workers =
+                    positive(value).ok_or_else(|| invalid("a whole number of at least 1"))?
+            }
+            "pool.max_workers" => {
+                self.max_workers =
+                    positive(value).ok_or_else(|| invalid("a whole number of at least 1"))?
+            }
+            "pool.queue_capacity" => {
+                self.queue_capacity =
+                    positive(value).ok_or_else(|| invalid("a whole number of at least 1"))?
+            }
+            "timeouts.idle" => {
+                self.idle_timeout = parse_duration(value)
+                    .filter(|d| !d.is_zero())
+                    .ok_or_else(|| invalid("a duration such as 5s or 500ms"))?
+            }
+            "timeouts.read" => {
+                self.read_timeout = parse_duration(value)
+                    .filter(|d| !d.is_zero())
+                    .ok_or_else(|| invalid("a duration such as 10s"))?
+            }
+            "timeouts.request" => {
+                self.request_timeout = parse_duration(value)
+                    .filter(|d| !d.is_zero())
+                    .ok_or_else(|| invalid("a duration such as 30s"))?
+            }
+            "timeouts.write" => {
+                self.write_timeout = parse_duration(value)
+                    .filter(|d| !d.is_zero())
+                    .ok_or_else(|| invalid("a duration such as 10s"))?
+            }
+            "limits.max_header_size" => {
+                // Too small to fit even a request line is surely a mistake.
+                self.max_header_size = parse_size(value)
+                    .and_then(|n| usize::try_from(n).ok())
+                    .filter(|&n| n >= 256)
+                    .ok_or_else(|| invalid("a size of at least 256 bytes, such as 16KB"))?
+            }
+            "limits.max_connections_per_ip" => {
+                let limit: usize = value.parse().map_err(|_| invalid("a whole number"))?;
+                self.max_connections_per_ip = Some(limit).filter(|&n| n > 0);
+            }
+            "timeouts.grace_period" => {
+                self.grace_period =
+                    parse_duration(value).ok_or_else(|| invalid("a duration such as 10s"))?
+            }
+            "access_log.path" => self.access_log = Some(PathBuf::from(value)),
+            "access_log.format" => {
+                self.access_log_format = match value.to_ascii_lowercase().as_str() {
+                    "common" => Format::Common,
+                    "combined" => Format::Combined,
+                    _ => return Err(invalid("common or combined")),
+                }
+            }
+            "access_log.max_size" => {
+                self.access_log_max_size = Some(
+                    parse_size(value)
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| invalid("a size such as 10MB or 512KB"))?,
+                )
+            }
+            "access_log.keep" => {
+                self.access_log_keep = value.parse().map_err(|_| invalid("a whole number"))?
+            }
+            "compression.enabled" => {
+                self.compression = value.parse().map_err(|_| invalid("true or false"))?
+            }
+            "compression.min_size" => {
+                self.compression_min_size = parse_size(value)
+                    .and_then(|n| usize::try_from(n).ok())
+                    .ok_or_else(|| invalid("a size such as 1KB"))?
+            }
+            "compression.level" => {
+                self.compression_level = value
+                    .parse()
+                    .ok()
+                    .filter(|&n| n <= 9)
+                    .ok_or_else(|| invalid("a level from 0 to 9"))?
+            }
+            "tls.cert" => self.tls_cert = Some(PathBuf::from(value)),
+            "tls.key" => self.tls_key = Some(PathBuf::from(value)),
+            "tls.redirect_port" => {
+                self.redirect_port = Some(
+                    value
+                        .parse()
+                        .map_err(|_| invalid("a port number from 0 to 65535"))?,
+                )
+            }
+            "log_level" => {
+                self.log_level = Level::from_str(value)
+                    .map_err(|_| invalid("one of error, warn, info or debug"))?
+            }
+            _ => return Err(ConfigError::UnknownSetting(name())),
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.workers > self.max_workers {
+            return Err(ConfigError::Inconsistent(format!(
+                "workers ({}) must not be more than max_workers ({})",
+                self.workers, self.max_workers
+            )));
+        }
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(_), None) => {
+                return Err(ConfigError::Inconsistent(
+                    "tls.cert is set but tls.key is not".to_string(),
+                ))
+            }
+            (None, Some(_)) => {
+                return Err(ConfigError::Inconsistent(
+                    "tls.key is set but tls.cert is not".to_string(),
+                ))
+            }
+            _ => {}
+        }
+        if self.tls_cert.is_some() && self.async_mode {
+            return Err(ConfigError::Inconsistent(
+                "TLS is not supported by the async server".to_string(),
+            ));
+        }
+        if self.redirect_port.is_some() && self.tls_cert.is_none() {
+            return Err(ConfigError::Inconsistent(
+                "tls.redirect_port needs tls.cert and tls.key".to_string(),
+            ));
+        }
+        if let Some(root) = &self.root {
+            if !root.is_dir() {
+                return Err(ConfigError::Inconsistent(format!(
+                    "document root {} is not a directory",
+                    root.display()
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn flatten<'a>(prefix: &str, table: &'a toml::Table, out: &mut Vec<(String, &'a toml::Value)>) {
+    for (name, value) in table {
+        let key = if prefix.is_empty() {
+            name.clone()
+        } else {
+            format!("{}.{}", prefix, name)
+        };
+        match value {
+            toml::Value::Table(inner) => flatten(&key, inner, out),
+            _ => out.push((key, value)),
+        }
+    }
+}
+
+// The file named by --config, in either the `--config path` or the
+// `--config=path` form.
+fn config_path(args: &[String]) -> Result<Option<&str>, ConfigError> {
+    for (i, arg) in args.iter().enumerate() {
+        if arg == "--config" {
+            return match args.get(i + 1) {
+                Some(path) => Ok(Some(path)),
+                None => Err(ConfigError::MissingValue(arg.clone())),
+            };
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+fn positive(value: &str) -> Option<usize> {
+    value.parse().ok().filter(|&n| n > 0)
+}
+
+// "250ms", "5s", "2m" or a bare number of seconds.
+fn parse_duration(value: &str) -> Option<Duration> {
+    let (number, unit) = split_unit(value)?;
+    match unit {
+        "ms" => Some(Duration::from_millis(number)),
+        "" | "s" => Some(Duration::from_secs(number)),
+        "m" => number.checked_mul(60).map(Duration::from_secs),
+        _ => None,
+    }
+}
+
+// "512KB", "10MB", "1GB" or a bare number of bytes. Units are powers of 1024.
+fn parse_size(value: &str) -> Option<u64> {
+    let (number, unit) = split_unit(value)?;
+    let shift = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" => 10,
+        "M" | "MB" => 20,
+        "G" | "GB" => 30,
+        _ => return None,
+    };
+    number.checked_mul(1 << shift)
+}
+
+fn split_unit(value: &str) -> Option<(u64, &str)> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    Some((number.parse().ok()?, unit.trim()))
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Help => f.write_str("help requested"),
+            ConfigError::Read(path, e) => write!(f, "cannot read {}: {}", path.display(), e),
+            ConfigError::Syntax(path, e) => {
+                write!(f, "{} is not valid TOML: {}", path.display(), e.message())
+            }
+            ConfigError::UnknownSetting(name) => write!(f, "unknown setting {}", name),
+            ConfigError::MissingValue(flag) => write!(f, "{} needs a value", flag),
+            ConfigError::Invalid {
+                setting,
+                value,
+                expected,
+            } => write!(
+                f,
+                "invalid value {:?} for {}: expected {}",
+                value, setting, expected
+            ),
+            ConfigError::Inconsistent(message) => f.write_str(message),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn flags_override_the_file() {
+        let mut config = Config::default();
+        config
+            .apply_toml(
+                "log_level = \"debug\"\n\
+                 [server]\nport = 8080\nasync = true\n\
+                 [pool]\nworkers = 2\n\
+                 [timeouts]\nidle = \"250ms\"\nrequest = \"1m\"\n\
+                 [limits]\nmax_connections_per_ip = 10\n",
+                Path::new("test.toml"),
+            )
+            .unwrap();
+        assert_eq!(8080, config.port);
+        assert!(config.async_mode);
+        assert_eq!(2, config.workers);
+        assert_eq!(Duration::from_millis(250), config.idle_timeout);
+        assert_eq!(Duration::from_secs(60), config.request_timeout);
+        assert_eq!(Some(10), config.max_connections_per_ip);
+        assert_eq!(Level::Debug, config.log_level);
+
+        let config = Config::from_args(args(&[
+            "--address",
+            "0.0.0.0",
+            "--port=9000",
+            "--grace-period",
+            "1m",
+            "--access-log=-",
+            "--access-log-max-size",
+            "2MB",
+            "--max-header-size=8KB",
+            "--max-connections-per-ip",
+            "0",
+            "src",
+        ]))
+        .unwrap();
+        assert_eq!(IpAddr::from([0, 0, 0, 0]), config.address);
+        assert_eq!(9000, config.port);
+        assert_eq!(Duration::from_secs(60), config.grace_period);
+        assert_eq!(Some(PathBuf::from("src")), config.root);
+        assert_eq!(Some(PathBuf::from("-")), config.access_log);
+        assert_eq!(Some(2 << 20), config.access_log_max_size);
+        assert_eq!(8 << 10, config.max_header_size);
+        assert_eq!(None, config.max_connections_per_ip);
+    }
+
+    #[test]
+    fn invalid_values_are_explained() {
+        let error = |list: &[&str]| Config::from_args(args(list)).unwrap_err().to_string();
+        assert_eq!(
+            "invalid value \"http\" for --port: expected a port number from 0 to 65535",
+            error(&["--port", "http"])
+        );
+        assert_eq!(
+            "invalid value \"0\" for --workers: expected a whole number of at least 1",
+            error(&["--workers", "0"])
+        );
+        assert_eq!(
+            "workers (8) must not be more than max_workers (4)",
+            error(&["--workers", "8", "--max-workers", "4"])
+        );
+        assert_eq!(
+            "invalid value \"0\" for --queue-capacity: expected a whole number of at least 1",
+            error(&["--queue-capacity", "0"])
+        );
+        assert_eq!("unknown setting --prot", error(&["--prot", "80"]));
+        assert!(error(&["--config=/nonexistent/hello.toml"])
+            .starts_with("cannot read /nonexistent/hello.toml"));
+        assert_eq!("--log-level needs a value", error(&["--log-level"]));
+        assert_eq!(
+            "tls.cert is set but tls.key is not",
+            error(&["--tls-cert", "cert.pem"])
+        );
+
+        let mut config = Config::default();
+        let toml_error = config
+            .apply_toml("[timeouts]\nidle = \"soon\"\n", Path::new("hello.toml"))
+            .unwrap_err();
+        assert_eq!(
+            "invalid value \"soon\" for timeouts.idle in hello.toml: \
+             expected a duration such as 5s or 500ms",
+            toml_error.to_string()
+        );
+        let unknown = config
+            .apply_toml("[server]\nprot = 80\n", Path::new("hello.toml"))
+            .unwrap_err();
+        assert_eq!(
+            "unknown setting server.prot in hello.toml",
+            unknown.to_string()
+        );
+    }
+}
@@ -1,46 +1,206 @@
+use hello::access_log::AccessLog;
+use hello::compression::Compression;
+use hello::config::{self, Config, ConfigError};
+use hello::log;
+use hello::log::Level;
+use hello::pool::{Event, OverflowPolicy};
+use hello::response::{Response, Status};
+use hello::router::Router;
+#[cfg(target_os = "linux")]
+use hello::runtime;
+use hello::server::{ConnectionConfig, Server};
+use hello::static_files::StaticFiles;
+use hello::tls::{self, Tls};
 use hello::ThreadPool;
-use std::fs;
-use std::io::prelude::*;
+use std::env;
 use std::net::TcpListener;
-use std::net::TcpStream;
+use std::path::Path;
+use std::process;
 use std::thread;
 use std::time::Duration;
 
 fn main() {
-    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
-    let pool = ThreadPool::new(4);
-    for stream in listener.incoming() {
-        let stream = stream.unwrap();
-        pool.execute(|| {
-            handle_connection(stream);
+    // Settings come from hello.toml (or --config) and command-line flags;
+    // see `cargo run -- --help`.
+    let config = match Config::from_args(env::args().skip(1)) {
+        Ok(config) => config,
+        Err(ConfigError::Help) => {
+            print!("{}", config::USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            eprintln!("Run with --help to see the available options.");
+            process::exit(2);
+        }
+    };
+    log::set_level(config.log_level);
+
+    let listener = TcpListener::bind((config.address, config.port)).unwrap_or_else(|e| {
+        log!(
+            Level::Error,
+            "Failed to bind {}:{}: {}",
+            config.address,
+            config.port,
+            e
+        );
+        process::exit(1);
+    });
+    let pool = ThreadPool::builder()
+        .min_threads(config.workers)
+        .max_threads(config.max_workers)
+        .keep_alive(Duration::from_secs(30))
+        .thread_name("hello-worker-")
+        // Beyond this many waiting connections, answer 503 right away.
+        .queue_capacity(config.queue_capacity)
+        .overflow_policy(OverflowPolicy::Reject)
+        // Panicked jobs come through here too, as the only report of them.
+        .event_handler(|event| match event {
+            Event::JobPanicked { .. } => log!(Level::Error, "{}", event),
+            _ => log!(Level::Debug, "{}", event),
+        })
+        .build()
+        .unwrap_or_else(|e| {
+            log!(Level::Error, "Failed to start thread pool: {}", e);
+            process::exit(1);
         });
+    let router = match &config.root {
+        Some(root) => static_routes(root),
+        None => routes(),
+    };
+    let mut server = Server::new(listener, pool, router)
+        .config(ConnectionConfig {
+            idle_timeout: config.idle_timeout,
+            read_timeout: config.read_timeout,
+            request_timeout: config.request_timeout,
+            write_timeout: config.write_timeout,
+            max_header_size: config.max_header_size,
+            max_connections_per_ip: config.max_connections_per_ip,
+            ..ConnectionConfig::default()
+        })
+        .grace_period(config.grace_period);
+    if config.compression {
+        server = server.compression(
+            Compression::new()
+                .min_size(config.compression_min_size)
+                .level(config.compression_level),
+        );
     }
-    println!("Shutting down.");
-}
+    if let Some(path) = &config.access_log {
+        server = server.access_log(access_log(&config, path));
+    }
+    if let (Some(cert), Some(key)) = (&config.tls_cert, &config.tls_key) {
+        let tls = Tls::from_pem_files(cert, key).unwrap_or_else(|e| {
+            log!(Level::Error, "Failed to load TLS certificate: {}", e);
+            process::exit(1);
+        });
+        server = server.tls(tls);
+    }
+    let addr = server.local_addr().unwrap_or_else(|e| {
+        log!(Level::Error, "Failed to get listening address: {}", e);
+        process::exit(1);
+    });
+    let scheme = if config.tls_cert.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+    log!(Level::Info, "Listening on {}://{}", scheme, addr);
+    let redirect = config
+        .redirect_port
+        .map(|port| redirect_server(&config, port, addr.port()));
+
+    // Ctrl-C (SIGINT) and SIGTERM stop accepting new connections and give
+    // in-flight requests a chance to finish.
+    let mut handles = vec![server.shutdown_handle()];
+    handles.extend(redirect.as_ref().map(|server| server.shutdown_handle()));
+    ctrlc::set_handler(move || handles.iter().for_each(|handle| handle.shutdown()))
+        .expect("failed to install signal handler");
+    let redirect = redirect.map(|server| {
+        let handle = server.shutdown_handle();
+        (handle, thread::spawn(move || server.run()))
+    });
 
-fn handle_connection(mut stream: TcpStream) {
-    let mut buffer = [0; 1024];
-    stream.read(&mut buffer).unwrap();
-
-    let get = b"GET / HTTP/1.1\r\n";
-    let sleep = b"GET /sleep HTTP/1.1\r\n";
-    let (status_line, filename) = if buffer.starts_with(get) {
-        ("HTTP/1.1 200 OK", "hello.html")
-    } else if buffer.starts_with(sleep) {
-        thread::sleep(Duration::from_secs(5));
-        ("HTTP/1.1 200 OK", "hello.html")
+    let result = if config.async_mode {
+        server.run_async()
     } else {
-        ("HTTP/1.1 404 NOT FOUND", "404.html")
+        server.run()
     };
+    // The main server may have stopped on an error rather than a signal,
+    // and then nothing else would stop the redirect server.
+    if let Some((handle, redirect)) = redirect {
+        handle.shutdown();
+        if let Ok(Err(e)) = redirect.join() {
+            log!(Level::Error, "Redirect server failed: {}", e);
+        }
+    }
+    if let Err(e) = result {
+        log!(Level::Error, "Server failed: {}", e);
+        process::exit(1);
+    }
+    log!(Level::Info, "Shutting down.");
+}
 
-    let contents = fs::read_to_string(filename).unwrap();
-    let response = format!(
-        "{}\r\nContent-Length: {}\r\n\r\n{}",
-        status_line,
-        contents.len(),
-        contents
+// Plain HTTP on `port` that sends everyone to the HTTPS server.
+fn redirect_server(config: &Config, port: u16, https_port: u16) -> Server {
+    let listener = TcpListener::bind((config.address, port)).unwrap_or_else(|e| {
+        log!(
+            Level::Error,
+            "Failed to bind {}:{}: {}",
+            config.address,
+            port,
+            e
+        );
+        process::exit(1);
+    });
+    log!(
+        Level::Info,
+        "Redirecting http://{}:{} to HTTPS",
+        config.address,
+        port
     );
+    Server::new(
+        listener,
+        ThreadPool::new(2),
+        tls::redirect_router(https_port),
+    )
+    .grace_period(config.grace_period)
+}
+
+fn access_log(config: &Config, path: &Path) -> AccessLog {
+    if path == Path::new("-") {
+        return AccessLog::stdout(config.access_log_format);
+    }
+    let log = AccessLog::file(path, config.access_log_format).unwrap_or_else(|e| {
+        log!(Level::Error, "Failed to open {}: {}", path.display(), e);
+        process::exit(1);
+    });
+    let log = log.keep(config.access_log_keep);
+    match config.access_log_max_size {
+        Some(bytes) => log.max_size(bytes),
+        None => log,
+    }
+}
+
+fn routes() -> Router {
+    let mut router = Router::new();
+    router
+        .get("/", |_| Response::file(Status::Ok, "hello.html"))
+        .not_found(|_| Response::file(Status::NotFound, "404.html"));
+    // Only holds a worker for the whole five seconds in threaded mode.
+    #[cfg(target_os = "linux")]
+    router.get_async("/sleep", |_| async {
+        runtime::sleep(Duration::from_secs(5)).await;
+        Response::file(Status::Ok, "hello.html")
+    });
+    router
+}
 
-    stream.write(response.as_bytes()).unwrap();
-    stream.flush().unwrap();
+fn static_routes(root: &Path) -> Router {
+    let files = StaticFiles::new(root);
+    let mut router = Router::new();
+    router.get("/*path", move |request| {
+        files.serve(request, request.param("path").unwrap_or(""))
+    });
+    router
 }
@@ -0,0 +1,119 @@
+use crate::request::Request;
+use crate::response::Response;
+
+// Code that runs around every request a Router handles, for things like
+// logging, authentication or CORS that apply to all routes. Add it with
+// Router::wrap. Middleware sees requests in the order it was added and
+// responses in the reverse order, so the first one added is the outermost:
+// it sees the request first and the response last.
+pub trait Middleware: Send + Sync + 'static {
+    // Runs before routing, so it may also rewrite the path or headers the
+    // router goes by. Returning a response skips the handler and any later
+    // middleware; the earlier ones still get to post-process it.
+    fn before(&self, _request: &mut Request) -> Option<Response> {
+        None
+    }
+
+    // Runs once there is a response. `request` is what the handler got,
+    // route params included, but without the body, which the handler may
+    // have taken.
+    fn after(&self, _request: &Request, _response: &mut Response) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::Status;
+    use crate::router::Router;
+    use std::sync::{Arc, Mutex};
+
+    // Records the order things happen in.
+    struct Trace {
+        name: &'static str,
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Middleware for Trace {
+        fn before(&self, _request: &mut Request) -> Option<Response> {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("{} before", self.name));
+            None
+        }
+
+        fn after(&self, request: &Request, response: &mut Response) {
+            self.events.lock().unwrap().push(format!(
+                "{} after {} {}",
+                self.name,
+                request.path,
+                response.status_code()
+            ));
+        }
+    }
+
+    struct RequireToken;
+
+    impl Middleware for RequireToken {
+        fn before(&self, request: &mut Request) -> Option<Response> {
+            match request.header("Authorization") {
+                Some("Bearer secret") => None,
+                _ => Some(Response::new(Status::Unauthorized, "Unauthorized\n")),
+            }
+        }
+
+        fn after(&self, _request: &Request, response: &mut Response) {
+            response.headers.append("X-Authenticated", "yes");
+        }
+    }
+
+    fn request(raw: &str) -> Request {
+        Request::read_from(&mut raw.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn runs_in_order_and_can_answer_early() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let trace = |name| Trace {
+            name,
+            events: Arc::clone(&events),
+        };
+        let handled = Arc::clone(&events);
+        let mut router = Router::new();
+        router
+            .get("/users/:id", move |request| {
+                handled.lock().unwrap().push("handler".to_string());
+                Response::new(Status::Ok, request.params["id"].clone())
+            })
+            .wrap(trace("outer"))
+            .wrap(RequireToken)
+            .wrap(trace("inner"));
+
+        let response = router.handle(request(
+            "GET /users/7 HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n",
+        ));
+        assert_eq!(b"7", &response.body[..]);
+        assert_eq!(Some("yes"), response.headers.get("X-Authenticated"));
+        assert_eq!(
+            vec![
+                "outer before",
+                "inner before",
+                "handler",
+                "inner after /users/7 200",
+                "outer after /users/7 200",
+            ],
+            *events.lock().unwrap()
+        );
+
+        // Turned away before the handler; only the middleware outside the
+        // one that answered sees the response.
+        events.lock().unwrap().clear();
+        let response = router.handle(request("GET /users/7 HTTP/1.1\r\n\r\n"));
+        assert_eq!(Status::Unauthorized, response.status);
+        assert!(!response.headers.contains("X-Authenticated"));
+        assert_eq!(
+            vec!["outer before", "outer after /users/7 401"],
+            *events.lock().unwrap()
+        );
+    }
+}
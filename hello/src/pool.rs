@@ -0,0 +1,983 @@
+mod builder;
+mod event;
+mod join_handle;
+mod parallel;
+mod scheduler;
+mod scope;
+mod stats;
+mod task;
+mod timer;
+
+pub use builder::{Builder, PoolCreationError};
+pub use event::Event;
+pub use join_handle::{JoinError, JoinHandle};
+pub use scheduler::{OverflowPolicy, Priority, QueueFullError};
+pub use scope::Scope;
+pub use stats::{Histogram, PoolStats};
+pub use timer::ScheduledHandle;
+
+use crate::log;
+use crate::log::Level;
+use scheduler::{Queued, Scheduler, Wait};
+use stats::Metrics;
+use std::cell::Cell;
+use std::io;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::{Mutex, MutexGuard, PoisonError, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use timer::{Task, Timer};
+
+pub struct ThreadPool {
+    shared: Arc<Shared>,
+    timer: Timer,
+}
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+// The shortest interval execute_every accepts.
+const MIN_INTERVAL: Duration = Duration::from_millis(1);
+
+// Called with the worker id and the panic message whenever a job panics.
+// The id is None for jobs that ran on the submitting thread.
+type PanicHandler = Box<dyn Fn(Option<usize>, &str) + Send + Sync + 'static>;
+
+// Sees everything the pool does; see Event.
+type EventHandler = Box<dyn Fn(&Event) + Send + Sync + 'static>;
+
+// Called with the worker id when a worker thread starts or stops.
+type ThreadHook = Arc<dyn Fn(usize) + Send + Sync + 'static>;
+
+// State shared between the pool and its workers. The workers live in here
+// too, so that a dying worker can put its replacement in its own slot.
+// There is one slot per possible worker; in an elastic pool the slots above
+// `live` are free until the queue backs up.
+struct Shared {
+    scheduler: Scheduler,
+    workers: Mutex<Vec<Worker>>,
+    live: AtomicUsize,
+    min_threads: usize,
+    max_threads: usize,
+    // How long a worker above the minimum may sit idle before it retires.
+    // None for fixed-size pools.
+    keep_alive: Option<Duration>,
+    panic_handler: RwLock<Option<PanicHandler>>,
+    shutting_down: AtomicBool,
+    thread_name: Option<String>,
+    stack_size: Option<usize>,
+    on_thread_start: Option<ThreadHook>,
+    on_thread_stop: Option<ThreadHook>,
+    event_handler: Option<EventHandler>,
+    metrics: Metrics,
+}
+
+impl ThreadPool {
+    pub fn new(size: usize) -> ThreadPool {
+        assert!(size > 0);
+
+        ThreadPool::builder()
+            .num_threads(size)
+            .build()
+            .expect("failed to spawn worker thread")
+    }
+
+    pub fn builder() -> Builder {
+        Builder::new()
+    }
+
+    fn from_builder(
+        builder: Builder,
+        min_threads: usize,
+        max_threads: usize,
+    ) -> Result<ThreadPool, PoolCreationError> {
+        let keep_alive = if min_threads < max_threads {
+            Some(builder.keep_alive)
+        } else {
+            None
+        };
+        let shared = Arc::new(Shared {
+            scheduler: Scheduler::new(
+                max_threads,
+                builder.queue_capacity,
+                builder.overflow_policy,
+                builder.priority_aging,
+            ),
+            workers: Mutex::new((0..max_threads).map(Worker::vacant).collect()),
+            live: AtomicUsize::new(0),
+            min_threads,
+            max_threads,
+            keep_alive,
+            panic_handler: RwLock::new(builder.panic_handler),
+            shutting_down: AtomicBool::new(false),
+            thread_name: builder.thread_name,
+            stack_size: builder.stack_size,
+            on_thread_start: builder.on_thread_start,
+            on_thread_stop: builder.on_thread_stop,
+            event_handler: builder.event_handler,
+            metrics: Metrics::new(),
+        });
+        let timer = Timer::new(Arc::clone(&shared)).map_err(PoolCreationError::Spawn)?;
+        // If a worker fails to start, dropping the pool stops the ones
+        // that already did.
+        let pool = ThreadPool { shared, timer };
+
+        for id in 0..min_threads {
+            let worker =
+                Worker::new(id, Arc::clone(&pool.shared)).map_err(PoolCreationError::Spawn)?;
+            lock(&pool.shared.workers)[id] = worker;
+            pool.shared.live.fetch_add(1, Ordering::SeqCst);
+        }
+        Ok(pool)
+    }
+
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.execute_with_priority(Priority::Normal, f);
+    }
+
+    pub fn execute_with_priority<F>(&self, priority: Priority, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        // A refused job has already been reported as Event::JobDropped.
+        let _ = self.try_execute_with_priority(priority, f);
+    }
+
+    // Submits a job, applying the overflow policy if the queue is full.
+    // Only the Reject policy makes this fail.
+    pub fn try_execute<F>(&self, f: F) -> Result<(), QueueFullError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.try_execute_with_priority(Priority::Normal, f)
+    }
+
+    pub fn try_execute_with_priority<F>(
+        &self,
+        priority: Priority,
+        f: F,
+    ) -> Result<(), QueueFullError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.shared
+            .submit(Box::new(f), priority, self.shared.current_worker())
+    }
+
+    // Runs the job once, after the delay has passed. Delays beyond a
+    // century are treated as a century.
+    pub fn execute_after<F>(&self, delay: Duration, f: F) -> ScheduledHandle
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.timer.schedule(delay, Task::Once(Box::new(f)))
+    }
+
+    // Runs the job every `interval`, starting one interval from now, until
+    // the handle is cancelled or the pool is dropped. If a run takes longer
+    // than the interval, the next one may overlap it on another worker.
+    // Intervals shorter than a millisecond are rounded up to one, so that
+    // Duration::ZERO can't flood the pool.
+    pub fn execute_every<F>(&self, interval: Duration, f: F) -> ScheduledHandle
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        let interval = interval.max(MIN_INTERVAL);
+        self.timer
+            .schedule(interval, Task::Every(interval, Arc::new(f)))
+    }
+
+    // Like execute, but hands back a JoinHandle for the job's result. A
+    // panic inside the job is caught and reported through the handle.
+    pub fn spawn<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.spawn_with_priority(Priority::Normal, f)
+    }
+
+    pub fn spawn_with_priority<F, T>(&self, priority: Priority, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        self.execute_with_priority(priority, move || {
+            match panic::catch_unwind(AssertUnwindSafe(f)) {
+                // The caller may have dropped the handle; that's fine.
+                Ok(value) => {
+                    let _ = sender.send(Ok(value));
+                }
+                // Hand the panic back to the worker as well, so the panic
+                // handler sees it like any other.
+                Err(payload) => {
+                    let message = join_handle::panic_message(payload.as_ref());
+                    let _ = sender.send(Err(JoinError::Panicked(message)));
+                    panic::resume_unwind(payload);
+                }
+            }
+        });
+        JoinHandle::new(receiver)
+    }
+
+    // Runs `f` with a Scope whose jobs may borrow from the caller, and
+    // returns once every job submitted through it has finished:
+    //
+    //     let mut totals = vec![0; chunks.len()];
+    //     pool.scope(|s| {
+    //         for (chunk, total) in chunks.iter().zip(&mut totals) {
+    //             s.execute(move || *total = chunk.iter().sum());
+    //         }
+    //     });
+    //
+    // If any of the jobs panicked, scope panics too, after the rest are done.
+    pub fn scope<'env, F, T>(&self, f: F) -> T
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+    {
+        scope::scope(&self.shared, f)
+    }
+
+    // Takes over panic reports from the event handler and the log; see
+    // report_panic.
+    pub fn set_panic_handler<F>(&self, handler: F)
+    where
+        F: Fn(Option<usize>, &str) + Send + Sync + 'static,
+    {
+        *self
+            .shared
+            .panic_handler
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Some(Box::new(handler));
+    }
+
+    // The number of worker threads currently running.
+    pub fn size(&self) -> usize {
+        self.shared.live.load(Ordering::SeqCst)
+    }
+
+    // The number of jobs waiting for a worker.
+    pub fn queue_depth(&self) -> usize {
+        self.shared.scheduler.pending()
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let metrics = &self.shared.metrics;
+        PoolStats {
+            workers: self.size(),
+            active_workers: metrics.active.load(Ordering::Relaxed),
+            queued_jobs: self.queue_depth(),
+            completed_jobs: metrics.completed.load(Ordering::Relaxed),
+            panicked_jobs: metrics.panicked.load(Ordering::Relaxed),
+            dropped_jobs: metrics.dropped.load(Ordering::Relaxed),
+            queue_latency: metrics.queue_latency.snapshot(),
+            run_time: metrics.run_time.snapshot(),
+        }
+    }
+}
+
+thread_local! {
+    // (pool, worker id) for worker threads, so that jobs submitted from
+    // inside a job can go straight onto the worker's own deque.
+    static CURRENT_WORKER: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // Stop the timer first so it can't submit anything while we drain.
+        self.timer.stop();
+
+        self.shared.shutting_down.store(true, Ordering::SeqCst);
+        self.shared.emit(Event::ShuttingDown);
+        self.shared.scheduler.terminate();
+
+        for id in 0..self.shared.max_threads {
+            // Don't hold the lock while joining; a dying worker needs it.
+            let thread = lock(&self.shared.workers)[id].thread.take();
+            if let Some(thread) = thread {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+impl Shared {
+    // `worker` is the id of the submitting worker thread, if any.
+    fn submit(
+        self: &Arc<Self>,
+        job: Job,
+        priority: Priority,
+        worker: Option<usize>,
+    ) -> Result<(), QueueFullError> {
+        match self.scheduler.push(job, priority, worker) {
+            Ok(dropped) => {
+                if let Some(dropped) = dropped {
+                    drop(dropped);
+                    self.job_dropped();
+                }
+                self.grow();
+                Ok(())
+            }
+            Err(job) if self.scheduler.policy() == OverflowPolicy::CallerRuns => {
+                self.run_job(
+                    None,
+                    Queued {
+                        job,
+                        queued_at: Instant::now(),
+                        droppable: true,
+                    },
+                );
+                Ok(())
+            }
+            Err(_) => {
+                self.job_dropped();
+                Err(QueueFullError)
+            }
+        }
+    }
+
+    // Queues a job that must not be refused or dropped, whatever the
+    // overflow policy. A worker's own deque is never bounded, so jobs from
+    // workers still go there.
+    fn submit_unbounded(self: &Arc<Self>, job: Job) {
+        match self.current_worker() {
+            Some(id) => {
+                // Can't fail: only the injector is bounded.
+                let _ = self.scheduler.push(job, Priority::Normal, Some(id));
+            }
+            None => self.scheduler.push_unbounded(job),
+        }
+        self.grow();
+    }
+
+    // The id of the calling thread if it is one of this pool's workers.
+    fn current_worker(self: &Arc<Self>) -> Option<usize> {
+        CURRENT_WORKER.with(|current| match current.get() {
+            Some((pool, id)) if pool == Arc::as_ptr(self) as usize => Some(id),
+            _ => None,
+        })
+    }
+
+    // Starts another worker if jobs are piling up faster than the idle
+    // workers can pick them up and we're still below the maximum.
+    fn grow(self: &Arc<Self>) {
+        let live = self.live.load(Ordering::SeqCst);
+        if live >= self.max_threads || self.scheduler.pending() <= self.scheduler.idle() {
+            return;
+        }
+        if self
+            .live
+            .compare_exchange(live, live + 1, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            // Someone else grew or shrank the pool; the next job will retry.
+            return;
+        }
+
+        let mut workers = lock(&self.workers);
+        let id = match workers.iter().position(|worker| !worker.active) {
+            Some(id) => id,
+            None => {
+                self.live.fetch_sub(1, Ordering::SeqCst);
+                return;
+            }
+        };
+        match Worker::new(id, Arc::clone(self)) {
+            Ok(worker) => workers[id] = worker,
+            Err(e) => {
+                self.live.fetch_sub(1, Ordering::SeqCst);
+                log!(Level::Error, "Failed to grow thread pool: {}", e);
+            }
+        }
+    }
+
+    // Called by a worker whose keep-alive ran out. Returns true if it may
+    // exit, which it may only do while the pool is above its minimum size.
+    fn try_retire(&self, id: usize) -> bool {
+        let mut workers = lock(&self.workers);
+        let live = self.live.load(Ordering::SeqCst);
+        if live <= self.min_threads
+            || self
+                .live
+                .compare_exchange(live, live - 1, Ordering::SeqCst, Ordering::SeqCst)
+                .is_err()
+        {
+            return false;
+        }
+        workers[id].active = false;
+        true
+    }
+
+    fn job_dropped(&self) {
+        self.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+        self.emit(Event::JobDropped);
+    }
+
+    // `worker` is None when the job runs on a caller's thread.
+    fn run_job(&self, worker: Option<usize>, queued: Queued) {
+        let metrics = &self.metrics;
+        let started = Instant::now();
+        let queued_for = started.saturating_duration_since(queued.queued_at);
+        metrics.queue_latency.record(queued_for);
+        self.emit(Event::JobStarted { worker, queued_for });
+
+        if worker.is_some() {
+            metrics.active.fetch_add(1, Ordering::Relaxed);
+        }
+        let result = panic::catch_unwind(AssertUnwindSafe(queued.job));
+        if worker.is_some() {
+            metrics.active.fetch_sub(1, Ordering::Relaxed);
+        }
+
+        let ran_for = started.elapsed();
+        metrics.run_time.record(ran_for);
+        match result {
+            Ok(()) => {
+                metrics.completed.fetch_add(1, Ordering::Relaxed);
+                self.emit(Event::JobFinished { worker, ran_for });
+            }
+            Err(payload) => {
+                metrics.panicked.fetch_add(1, Ordering::Relaxed);
+                let message = join_handle::panic_message(payload.as_ref());
+                self.report_panic(worker, &message);
+            }
+        }
+    }
+
+    fn emit(&self, event: Event) {
+        if let Some(handler) = &self.event_handler {
+            handler(&event);
+        }
+    }
+
+    // Each panic is reported once: to the panic handler if there is one,
+    // otherwise as Event::JobPanicked if there is an event handler, and
+    // otherwise logged as an error.
+    fn report_panic(&self, worker: Option<usize>, message: &str) {
+        let handler = self
+            .panic_handler
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        let event = Event::JobPanicked { worker, message };
+        match (handler.as_ref(), &self.event_handler) {
+            (Some(handler), _) => handler(worker, message),
+            (None, Some(_)) => self.emit(event),
+            (None, None) => log!(Level::Error, "{}", event),
+        }
+    }
+}
+
+// A poisoned lock only means some other thread panicked while holding it;
+// none of the state behind our locks can be left half-updated, so carry on.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+struct Worker {
+    id: usize,
+    thread: Option<thread::JoinHandle<()>>,
+    // False for slots that have no running worker. A retired worker's
+    // handle stays in its slot until the slot is reused.
+    active: bool,
+}
+
+impl Worker {
+    fn vacant(id: usize) -> Worker {
+        Worker {
+            id,
+            thread: None,
+            active: false,
+        }
+    }
+
+    fn new(id: usize, shared: Arc<Shared>) -> io::Result<Worker> {
+        let mut builder = thread::Builder::new();
+        if let Some(prefix) = &shared.thread_name {
+            builder = builder.name(format!("{}{}", prefix, id));
+        }
+        if let Some(stack_size) = shared.stack_size {
+            builder = builder.stack_size(stack_size);
+        }
+
+        let thread = builder.spawn(move || {
+            if let Some(hook) = &shared.on_thread_start {
+                hook(id);
+            }
+            shared.emit(Event::WorkerStarted { worker: id });
+            let sentinel = Sentinel {
+                id,
+                shared: Arc::clone(&shared),
+            };
+            CURRENT_WORKER.with(|current| {
+                current.set(Some((Arc::as_ptr(&shared) as usize, id)));
+            });
+            loop {
+                match shared.scheduler.wait(id, shared.keep_alive) {
+                    Wait::Job(job) => shared.run_job(Some(id), job),
+                    Wait::Idle => {
+                        if shared.try_retire(id) {
+                            shared.emit(Event::WorkerRetired { worker: id });
+                            // A job submitted while we were still counted
+                            // as idle didn't grow the pool, since it expected
+                            // us to take it. Start a worker for it instead.
+                            if shared.scheduler.pending() > 0 {
+                                shared.grow();
+                            }
+                            break;
+                        }
+                    }
+                    Wait::Terminate => {
+                        shared.emit(Event::WorkerStopped { worker: id });
+                        break;
+                    }
+                }
+            }
+            drop(sentinel);
+        })?;
+        Ok(Worker {
+            id,
+            thread: Some(thread),
+            active: true,
+        })
+    }
+}
+
+// Job panics are caught, but a worker can still die, e.g. if the panic
+// handler itself panics. The sentinel notices the unwinding thread and
+// starts a replacement so the pool keeps its configured size. It is only
+// armed after the start hook ran, so a panicking start hook can't turn
+// into an endless respawn loop.
+struct Sentinel {
+    id: usize,
+    shared: Arc<Shared>,
+}
+
+impl Drop for Sentinel {
+    fn drop(&mut self) {
+        if let Some(hook) = &self.shared.on_thread_stop {
+            hook(self.id);
+        }
+        if !thread::panicking() || self.shared.shutting_down.load(Ordering::SeqCst) {
+            return;
+        }
+        self.shared.emit(Event::WorkerDied { worker: self.id });
+        let mut workers = lock(&self.shared.workers);
+        match Worker::new(self.id, Arc::clone(&self.shared)) {
+            Ok(worker) => workers[self.id] = worker,
+            Err(e) => {
+                workers[self.id].active = false;
+                self.shared.live.fetch_sub(1, Ordering::SeqCst);
+                log!(Level::Error, "Failed to replace worker {}: {}", self.id, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panics_reach_the_panic_handler() {
+        let pool = ThreadPool::new(1);
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        pool.set_panic_handler(move |_, message| {
+            sender.lock().unwrap().send(message.to_string()).unwrap();
+        });
+
+        pool.execute(|| panic!("from execute"));
+        let handle = pool.spawn(|| -> () { panic!("from spawn") });
+        assert!(handle.join().is_err());
+
+        let mut messages = vec![receiver.recv().unwrap(), receiver.recv().unwrap()];
+        messages.sort();
+        assert_eq!(vec!["from execute", "from spawn"], messages);
+    }
+
+    #[test]
+    fn builder_configures_workers() {
+        let (sender, receiver) = mpsc::channel();
+        let starts = Mutex::new(sender.clone());
+        let stops = Mutex::new(sender);
+        let pool = ThreadPool::builder()
+            .num_threads(2)
+            .thread_name("test-worker-")
+            .stack_size(256 * 1024)
+            .on_thread_start(move |id| {
+                starts
+                    .lock()
+                    .unwrap()
+                    .send(format!("start {}", id))
+                    .unwrap()
+            })
+            .on_thread_stop(move |id| stops.lock().unwrap().send(format!("stop {}", id)).unwrap())
+            .build()
+            .unwrap();
+
+        let name = pool.spawn(|| thread::current().name().map(str::to_string));
+        assert!(name.join().unwrap().unwrap().starts_with("test-worker-"));
+        drop(pool);
+
+        let mut events: Vec<String> = receiver.iter().collect();
+        events.sort();
+        assert_eq!(vec!["start 0", "start 1", "stop 0", "stop 1"], events);
+    }
+
+    #[test]
+    fn builder_rejects_zero_threads() {
+        assert!(matches!(
+            ThreadPool::builder().num_threads(0).build(),
+            Err(PoolCreationError::ZeroThreads)
+        ));
+        assert!(matches!(
+            ThreadPool::builder().queue_capacity(0).build(),
+            Err(PoolCreationError::ZeroCapacity)
+        ));
+    }
+
+    #[test]
+    fn jobs_spawned_by_jobs_are_shared_between_workers() {
+        let pool = Arc::new(ThreadPool::new(4));
+        let inner = Arc::clone(&pool);
+        let outer = pool.spawn(move || {
+            // These land on the submitting worker's own deque; the idle
+            // workers have to steal them.
+            let handles: Vec<_> = (0..16u64)
+                .map(|i| {
+                    inner.spawn(move || {
+                        thread::sleep(std::time::Duration::from_millis(10));
+                        (i, thread::current().id())
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        let results = outer.join().unwrap();
+        let sum: u64 = results.iter().map(|(i, _)| i).sum();
+        assert_eq!(120, sum);
+        let threads: std::collections::HashSet<_> = results.iter().map(|(_, id)| *id).collect();
+        assert!(threads.len() > 1);
+    }
+
+    #[test]
+    fn overflow_policies() {
+        // One worker, parked on a gate, and room for one queued job.
+        fn blocked_pool(policy: OverflowPolicy) -> (ThreadPool, mpsc::Sender<()>) {
+            let pool = ThreadPool::builder()
+                .num_threads(1)
+                .queue_capacity(1)
+                .overflow_policy(policy)
+                .build()
+                .unwrap();
+            let (release, gate) = mpsc::channel::<()>();
+            let (started, running) = mpsc::channel();
+            pool.execute(move || {
+                started.send(()).unwrap();
+                gate.recv().unwrap();
+            });
+            running.recv().unwrap();
+            (pool, release)
+        }
+
+        let (pool, release) = blocked_pool(OverflowPolicy::Reject);
+        assert_eq!(Ok(()), pool.try_execute(|| {}));
+        assert_eq!(Err(QueueFullError), pool.try_execute(|| {}));
+        release.send(()).unwrap();
+
+        let (pool, release) = blocked_pool(OverflowPolicy::DropOldest);
+        let mut oldest = pool.spawn(|| "oldest");
+        let newest = pool.spawn(|| "newest");
+        assert_eq!(Some(Err(JoinError::Cancelled)), oldest.try_join());
+        release.send(()).unwrap();
+        assert_eq!(Ok("newest"), newest.join());
+
+        let (pool, release) = blocked_pool(OverflowPolicy::CallerRuns);
+        pool.execute(|| {});
+        let caller = thread::current().id();
+        let ran_on = pool.spawn(move || thread::current().id() == caller);
+        assert_eq!(Ok(true), ran_on.join());
+        release.send(()).unwrap();
+
+        let (pool, release) = blocked_pool(OverflowPolicy::Block);
+        pool.execute(|| {});
+        let releaser = thread::spawn(move || {
+            thread::sleep(std::time::Duration::from_millis(50));
+            release.send(()).unwrap();
+        });
+        // Blocks until the gate opens and the worker drains the queue.
+        assert_eq!(Ok(1), pool.spawn(|| 1).join());
+        releaser.join().unwrap();
+    }
+
+    #[test]
+    fn higher_priority_jobs_run_first() {
+        let pool = ThreadPool::builder()
+            .num_threads(1)
+            .priority_aging(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        let (release, gate) = mpsc::channel::<()>();
+        let (started, running) = mpsc::channel();
+        pool.execute(move || {
+            started.send(()).unwrap();
+            gate.recv().unwrap();
+        });
+        running.recv().unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let submit = |priority, name: &'static str| {
+            let sender = Mutex::new(sender.clone());
+            pool.execute_with_priority(priority, move || {
+                sender.lock().unwrap().send(name).unwrap();
+            });
+        };
+        // Aging lifts this one above anything submitted after it.
+        submit(Priority::Low, "aged");
+        thread::sleep(Duration::from_millis(160));
+        submit(Priority::Low, "low");
+        submit(Priority::Normal, "normal");
+        submit(Priority::High, "high");
+        release.send(()).unwrap();
+
+        let order: Vec<_> = receiver.iter().take(4).collect();
+        assert_eq!(vec!["aged", "high", "normal", "low"], order);
+    }
+
+    #[test]
+    fn stats_and_events() {
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let pool = ThreadPool::builder()
+            .num_threads(1)
+            .event_handler(move |event| {
+                sender.lock().unwrap().send(event.to_string()).unwrap();
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(Ok(1), pool.spawn(|| 1).join());
+        assert!(pool.spawn(|| -> () { panic!("boom") }).join().is_err());
+        // The handle is told before the worker finishes its bookkeeping.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.stats().active_workers > 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let stats = pool.stats();
+        assert_eq!(1, stats.workers);
+        assert_eq!(0, stats.active_workers);
+        assert_eq!(0, stats.queued_jobs);
+        assert_eq!(
+            (1, 1, 0),
+            (
+                stats.completed_jobs,
+                stats.panicked_jobs,
+                stats.dropped_jobs
+            )
+        );
+        assert_eq!(2, stats.queue_latency.count());
+        assert_eq!(2, stats.run_time.count());
+
+        drop(pool);
+        let events: Vec<String> = receiver.iter().collect();
+        assert_eq!("Worker 0 started.", events[0]);
+        assert!(events.contains(&"Worker 0 job panicked: boom".to_string()));
+        assert_eq!(
+            &events[events.len() - 2..],
+            &[
+                "Telling all workers to terminate.",
+                "Worker 0 was told to terminate."
+            ]
+        );
+    }
+
+    #[test]
+    fn scoped_jobs_borrow_from_the_caller() {
+        let pool = ThreadPool::new(4);
+        let numbers: Vec<u64> = (1..=100).collect();
+        let mut sums = vec![0; 4];
+        pool.scope(|s| {
+            for (chunk, sum) in numbers.chunks(25).zip(&mut sums) {
+                s.execute(move || *sum = chunk.iter().sum());
+            }
+        });
+        assert_eq!(vec![325, 950, 1575, 2200], sums);
+
+        // A scope opened inside a job on a one-worker pool can only finish
+        // if the waiting worker runs the scoped jobs itself.
+        let pool = Arc::new(ThreadPool::new(1));
+        let inner = Arc::clone(&pool);
+        let nested = pool.spawn(move || {
+            let mut flags = [false; 3];
+            inner.scope(|s| {
+                for flag in flags.iter_mut() {
+                    s.execute(move || *flag = true);
+                }
+            });
+            flags
+        });
+        assert_eq!(Ok([true; 3]), nested.join());
+
+        pool.set_panic_handler(|_, _| {});
+        let finished = AtomicUsize::new(0);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.scope(|s| {
+                s.execute(|| panic!("scoped panic"));
+                s.execute(|| {
+                    thread::sleep(Duration::from_millis(20));
+                    finished.fetch_add(1, Ordering::SeqCst);
+                });
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(1, finished.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn scoped_jobs_ignore_the_queue_capacity() {
+        for policy in [OverflowPolicy::Reject, OverflowPolicy::DropOldest] {
+            let pool = ThreadPool::builder()
+                .num_threads(1)
+                .queue_capacity(1)
+                .overflow_policy(policy)
+                .build()
+                .unwrap();
+            let ran = AtomicUsize::new(0);
+            pool.scope(|s| {
+                for _ in 0..10 {
+                    s.execute(|| {
+                        ran.fetch_add(1, Ordering::SeqCst);
+                    });
+                }
+            });
+            assert_eq!(10, ran.into_inner());
+            assert_eq!(0, pool.stats().dropped_jobs);
+        }
+    }
+
+    #[test]
+    fn elastic_pool_grows_and_shrinks() {
+        let pool = ThreadPool::builder()
+            .min_threads(1)
+            .max_threads(4)
+            .keep_alive(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        assert_eq!(1, pool.size());
+
+        let (release, gate) = mpsc::channel::<()>();
+        let gate = Arc::new(Mutex::new(gate));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let gate = Arc::clone(&gate);
+                pool.spawn(move || gate.lock().unwrap().recv().unwrap())
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(4, pool.size());
+
+        for _ in 0..4 {
+            release.send(()).unwrap();
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(0, pool.queue_depth());
+
+        // The extra workers retire after the keep-alive, the minimum stays.
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while pool.size() > 1 && std::time::Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(1, pool.size());
+        assert_eq!(Ok(5), pool.spawn(|| 5).join());
+    }
+
+    #[test]
+    fn delayed_and_periodic_jobs() {
+        let pool = ThreadPool::new(2);
+        let (sender, receiver) = mpsc::channel();
+
+        let start = std::time::Instant::now();
+        let later = Mutex::new(sender.clone());
+        pool.execute_after(Duration::from_millis(50), move || {
+            later.lock().unwrap().send("later").unwrap();
+        });
+        let cancelled = Mutex::new(sender.clone());
+        pool.execute_after(Duration::from_millis(20), move || {
+            cancelled.lock().unwrap().send("cancelled").unwrap();
+        })
+        .cancel();
+        assert_eq!("later", receiver.recv().unwrap());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        let every = Mutex::new(sender);
+        let ticker = pool.execute_every(Duration::from_millis(10), move || {
+            let _ = every.lock().unwrap().send("tick");
+        });
+        for _ in 0..3 {
+            assert_eq!("tick", receiver.recv().unwrap());
+        }
+        ticker.cancel();
+        thread::sleep(Duration::from_millis(30));
+        while receiver.try_recv().is_ok() {}
+        assert!(receiver.recv_timeout(Duration::from_millis(50)).is_err());
+
+        // A zero interval is rounded up instead of spinning.
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&runs);
+        let ticker = pool.execute_every(Duration::ZERO, move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        thread::sleep(Duration::from_millis(20));
+        ticker.cancel();
+        let runs = runs.load(Ordering::SeqCst);
+        assert!(runs > 0 && runs <= 25, "{} runs", runs);
+
+        // Delays too long to add to the clock just never come due.
+        pool.execute_after(Duration::MAX, || panic!("ran"));
+        pool.execute_every(Duration::MAX, || panic!("ran"));
+        assert_eq!(Ok(1), pool.spawn(|| 1).join());
+    }
+
+    #[test]
+    fn replaces_workers_that_die() {
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let pool = ThreadPool::builder()
+            .num_threads(2)
+            .on_thread_start(move |id| {
+                let _ = sender.lock().unwrap().send((id, thread::current().id()));
+            })
+            .panic_handler(|_, _| panic!("the handler itself panics"))
+            .build()
+            .unwrap();
+        let started: Vec<_> = receiver.iter().take(2).collect();
+        for _ in 0..4 {
+            pool.execute(|| panic!("job panic"));
+        }
+
+        // Every death starts a new thread in the dead worker's slot.
+        let replacements: Vec<_> = receiver.iter().take(4).collect();
+        for (id, thread) in &replacements {
+            assert!(*id < 2);
+            assert!(started.iter().all(|(_, original)| original != thread));
+        }
+        let results: Vec<_> = (0..8).map(|i| pool.spawn(move || i)).collect();
+        for (i, result) in results.into_iter().enumerate() {
+            assert_eq!(Ok(i), result.join());
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use super::{Event, EventHandler, OverflowPolicy, PanicHandler, ThreadHook, ThreadPool};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum PoolCreationError {
+    ZeroThreads,
+    // min_threads was set above max_threads.
+    InvalidThreadRange,
+    // queue_capacity was set to 0, which no job could ever get past.
+    ZeroCapacity,
+    // The OS refused to start one of the worker threads.
+    Spawn(io::Error),
+}
+
+impl fmt::Display for PoolCreationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PoolCreationError::ZeroThreads => write!(f, "a thread pool needs at least one thread"),
+            PoolCreationError::InvalidThreadRange => {
+                write!(f, "min_threads must not be greater than max_threads")
+            }
+            PoolCreationError::ZeroCapacity => {
+                write!(f, "a bounded queue needs room for at least one job")
+            }
+            PoolCreationError::Spawn(e) => write!(f, "failed to spawn worker thread: {}", e),
+        }
+    }
+}
+
+impl Error for PoolCreationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PoolCreationError::ZeroThreads
+            | PoolCreationError::InvalidThreadRange
+            | PoolCreationError::ZeroCapacity => None,
+            PoolCreationError::Spawn(e) => Some(e),
+        }
+    }
+}
+
+pub struct Builder {
+    pub(super) num_threads: Option<usize>,
+    pub(super) min_threads: Option<usize>,
+    pub(super) max_threads: Option<usize>,
+    pub(super) keep_alive: Duration,
+    pub(super) thread_name: Option<String>,
+    pub(super) stack_size: Option<usize>,
+    pub(super) queue_capacity: Option<usize>,
+    pub(super) overflow_policy: OverflowPolicy,
+    pub(super) priority_aging: Option<Duration>,
+    pub(super) on_thread_start: Option<ThreadHook>,
+    pub(super) on_thread_stop: Option<ThreadHook>,
+    pub(super) panic_handler: Option<PanicHandler>,
+    pub(super) event_handler: Option<EventHandler>,
+}
+
+impl Builder {
+    pub fn new() -> Builder {
+        Builder {
+            num_threads: None,
+            min_threads: None,
+            max_threads: None,
+            keep_alive: Duration::from_secs(60),
+            thread_name: None,
+            stack_size: None,
+            queue_capacity: None,
+            overflow_policy: OverflowPolicy::default(),
+            priority_aging: Some(Duration::from_secs(1)),
+            on_thread_start: None,
+            on_thread_stop: None,
+            panic_handler: None,
+            event_handler: None,
+        }
+    }
+
+    // A fixed number of workers. Defaults to the number of CPUs available
+    // to the process.
+    pub fn num_threads(mut self, num_threads: usize) -> Builder {
+        self.num_threads = Some(num_threads);
+        self
+    }
+
+    // Setting min_threads and max_threads apart makes the pool elastic: it
+    // starts with the minimum, adds workers while jobs are queueing up, and
+    // retires the extra ones after they've been idle for keep_alive.
+    pub fn min_threads(mut self, min_threads: usize) -> Builder {
+        self.min_threads = Some(min_threads);
+        self
+    }
+
+    pub fn max_threads(mut self, max_threads: usize) -> Builder {
+        self.max_threads = Some(max_threads);
+        self
+    }
+
+    pub fn keep_alive(mut self, keep_alive: Duration) -> Builder {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    // Workers are named `<prefix><id>`, which shows up in panic messages
+    // and debuggers.
+    pub fn thread_name(mut self, prefix: &str) -> Builder {
+        self.thread_name = Some(prefix.to_string());
+        self
+    }
+
+    pub fn stack_size(mut self, bytes: usize) -> Builder {
+        self.stack_size = Some(bytes);
+        self
+    }
+
+    // Limits how many submitted jobs may wait for a worker. Unbounded by
+    // default; 0 is refused by build.
+    pub fn queue_capacity(mut self, capacity: usize) -> Builder {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    pub fn overflow_policy(mut self, policy: OverflowPolicy) -> Builder {
+        self.overflow_policy = policy;
+        self
+    }
+
+    // How long a queued job waits before it is treated as one priority level
+    // higher. One second by default; Duration::ZERO turns aging off.
+    pub fn priority_aging(mut self, interval: Duration) -> Builder {
+        self.priority_aging = Some(interval).filter(|interval| !interval.is_zero());
+        self
+    }
+
+    // Runs on each worker thread, with its id, before it takes any jobs.
+    pub fn on_thread_start<F>(mut self, hook: F) -> Builder
+    where
+        F: Fn(usize) + Send + Sync + 'static,
+    {
+        self.on_thread_start = Some(Arc::new(hook));
+        self
+    }
+
+    // Runs on each worker thread, with its id, just before it exits.
+    pub fn on_thread_stop<F>(mut self, hook: F) -> Builder
+    where
+        F: Fn(usize) + Send + Sync + 'static,
+    {
+        self.on_thread_stop = Some(Arc::new(hook));
+        self
+    }
+
+    // Called with the worker id and message for every job that panics.
+    // Without one, panics go to the event handler as Event::JobPanicked, or
+    // to the log if there is no event handler either.
+    pub fn panic_handler<F>(mut self, handler: F) -> Builder
+    where
+        F: Fn(Option<usize>, &str) + Send + Sync + 'static,
+    {
+        self.panic_handler = Some(Box::new(handler));
+        self
+    }
+
+    // The pool is silent by default. To log what it does, print the events:
+    // `.event_handler(|event| eprintln!("{}", event))`.
+    pub fn event_handler<F>(mut self, handler: F) -> Builder
+    where
+        F: Fn(&Event) + Send + Sync + 'static,
+    {
+        self.event_handler = Some(Box::new(handler));
+        self
+    }
+
+    pub fn build(self) -> Result<ThreadPool, PoolCreationError> {
+        let num_threads = self
+            .num_threads
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+        let (min_threads, max_threads) = match (self.min_threads, self.max_threads) {
+            (Some(min), Some(max)) => (min, max),
+            (Some(min), None) => (min, num_threads.max(min)),
+            (None, Some(max)) => (num_threads.min(max), max),
+            (None, None) => (num_threads, num_threads),
+        };
+        if max_threads == 0 {
+            return Err(PoolCreationError::ZeroThreads);
+        }
+        if min_threads > max_threads {
+            return Err(PoolCreationError::InvalidThreadRange);
+        }
+        if self.queue_capacity == Some(0) {
+            return Err(PoolCreationError::ZeroCapacity);
+        }
+        ThreadPool::from_builder(self, min_threads, max_threads)
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder::new()
+    }
+}
@@ -0,0 +1,85 @@
+use std::fmt;
+use std::time::Duration;
+
+// Something that happened inside the pool, passed to the event handler set
+// with Builder::event_handler. The handler runs on whichever thread the
+// event happened on, often a worker in the middle of its loop, so it should
+// be quick. The Display impl gives a one-line description for logs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Event<'a> {
+    WorkerStarted {
+        worker: usize,
+    },
+    // An elastic pool's worker was idle for its keep-alive.
+    WorkerRetired {
+        worker: usize,
+    },
+    // The worker finished the queue after the pool was dropped.
+    WorkerStopped {
+        worker: usize,
+    },
+    // The worker thread panicked outside of a job; a replacement is started.
+    WorkerDied {
+        worker: usize,
+    },
+    // `worker` is None for jobs run on the submitting thread.
+    JobStarted {
+        worker: Option<usize>,
+        queued_for: Duration,
+    },
+    JobFinished {
+        worker: Option<usize>,
+        ran_for: Duration,
+    },
+    // Only sent when the pool has no panic handler, which would otherwise
+    // get the panic instead.
+    JobPanicked {
+        worker: Option<usize>,
+        message: &'a str,
+    },
+    // A job was refused or thrown out because the queue was full.
+    JobDropped,
+    ShuttingDown,
+}
+
+impl fmt::Display for Event<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::WorkerStarted { worker } => write!(f, "Worker {} started.", worker),
+            Event::WorkerRetired { worker } => {
+                write!(f, "Worker {} was idle for too long; retiring.", worker)
+            }
+            Event::WorkerStopped { worker } => {
+                write!(f, "Worker {} was told to terminate.", worker)
+            }
+            Event::WorkerDied { worker } => {
+                write!(f, "Worker {} died; starting a replacement.", worker)
+            }
+            Event::JobStarted {
+                worker: Some(id), ..
+            } => write!(f, "Worker {} got a job; executing.", id),
+            Event::JobStarted { worker: None, .. } => {
+                write!(f, "Running a job on the calling thread.")
+            }
+            Event::JobFinished {
+                worker: Some(id),
+                ran_for,
+            } => write!(f, "Worker {} finished a job in {:?}.", id, ran_for),
+            Event::JobFinished {
+                worker: None,
+                ran_for,
+            } => write!(f, "Job on the calling thread finished in {:?}.", ran_for),
+            Event::JobPanicked {
+                worker: Some(id),
+                message,
+            } => write!(f, "Worker {} job panicked: {}", id, message),
+            Event::JobPanicked {
+                worker: None,
+                message,
+            } => write!(f, "Job panicked on the calling thread: {}", message),
+            Event::JobDropped => write!(f, "Dropping job: thread pool queue is full"),
+            Event::ShuttingDown => write!(f, "Telling all workers to terminate."),
+        }
+    }
+}
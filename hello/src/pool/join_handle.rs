@@ -0,0 +1,109 @@
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::sync::mpsc;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JoinError {
+    // The job panicked; this is the panic message, if it had one.
+    Panicked(String),
+    // The job was dropped without running, e.g. because the pool shut down,
+    // or its result has already been taken from this handle.
+    Cancelled,
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JoinError::Panicked(message) => write!(f, "job panicked: {}", message),
+            JoinError::Cancelled => write!(f, "job was cancelled"),
+        }
+    }
+}
+
+impl Error for JoinError {}
+
+// A handle to the result of a job submitted with ThreadPool::spawn, in the
+// spirit of std::thread::JoinHandle.
+pub struct JoinHandle<T> {
+    receiver: mpsc::Receiver<Result<T, JoinError>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub(crate) fn new(receiver: mpsc::Receiver<Result<T, JoinError>>) -> JoinHandle<T> {
+        JoinHandle { receiver }
+    }
+
+    // Blocks until the job has finished.
+    pub fn join(self) -> Result<T, JoinError> {
+        match self.receiver.recv() {
+            Ok(result) => result,
+            Err(_) => Err(JoinError::Cancelled),
+        }
+    }
+
+    // Returns None if the job hasn't finished yet.
+    pub fn try_join(&mut self) -> Option<Result<T, JoinError>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result),
+            Err(mpsc::TryRecvError::Empty) => None,
+            Err(mpsc::TryRecvError::Disconnected) => Some(Err(JoinError::Cancelled)),
+        }
+    }
+
+    // Returns None if the job didn't finish within the timeout.
+    pub fn join_timeout(&mut self, timeout: Duration) -> Option<Result<T, JoinError>> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(result) => Some(result),
+            Err(mpsc::RecvTimeoutError::Timeout) => None,
+            Err(mpsc::RecvTimeoutError::Disconnected) => Some(Err(JoinError::Cancelled)),
+        }
+    }
+}
+
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("Box<dyn Any>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pool::{JoinError, ThreadPool};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn returns_the_job_result() {
+        let pool = ThreadPool::new(2);
+        let handle = pool.spawn(|| 2 + 2);
+        assert_eq!(Ok(4), handle.join());
+    }
+
+    #[test]
+    fn reports_panics_as_errors() {
+        let pool = ThreadPool::new(1);
+        let handle = pool.spawn(|| -> u32 { panic!("boom") });
+        assert_eq!(Err(JoinError::Panicked("boom".to_string())), handle.join());
+
+        // The worker survived the panic and can still run jobs.
+        assert_eq!(Ok("still here"), pool.spawn(|| "still here").join());
+    }
+
+    #[test]
+    fn try_join_and_join_timeout_wait_for_the_job() {
+        let pool = ThreadPool::new(1);
+        let (release, gate) = mpsc::channel::<()>();
+        let mut handle = pool.spawn(move || gate.recv().map(|_| 7).unwrap_or(0));
+
+        assert_eq!(None, handle.try_join());
+        assert_eq!(None, handle.join_timeout(Duration::from_millis(20)));
+        release.send(()).unwrap();
+        assert_eq!(Some(Ok(7)), handle.join_timeout(Duration::from_secs(5)));
+    }
+}
@@ -0,0 +1,120 @@
+use super::ThreadPool;
+
+// Chunks per worker. More than one, so that a worker that draws an easy
+// chunk can steal part of the remaining work instead of sitting idle.
+const CHUNKS_PER_WORKER: usize = 4;
+
+// Data-parallel helpers built on scope. They take anything iterable,
+// slices included (as `&slice` or `slice.iter()`), split the items into
+// contiguous chunks, and keep the results in the order of the input. Like
+// scope, they panic if `f` panics for any of the items.
+impl ThreadPool {
+    pub fn par_map<I, F, R>(&self, items: I, f: F) -> Vec<R>
+    where
+        I: IntoIterator,
+        I::Item: Send,
+        F: Fn(I::Item) -> R + Sync,
+        R: Send,
+    {
+        let chunks = self.chunk(items);
+        let mut results: Vec<Vec<R>> = chunks.iter().map(|_| Vec::new()).collect();
+        let f = &f;
+        self.scope(|s| {
+            for (chunk, result) in chunks.into_iter().zip(&mut results) {
+                s.execute(move || *result = chunk.into_iter().map(f).collect());
+            }
+        });
+        results.into_iter().flatten().collect()
+    }
+
+    pub fn par_for_each<I, F>(&self, items: I, f: F)
+    where
+        I: IntoIterator,
+        I::Item: Send,
+        F: Fn(I::Item) + Sync,
+    {
+        let f = &f;
+        self.scope(|s| {
+            for chunk in self.chunk(items) {
+                s.execute(move || chunk.into_iter().for_each(f));
+            }
+        });
+    }
+
+    // Combines all items with `f`, or returns None if there are none. Each
+    // chunk is reduced left to right and the chunk results are combined in
+    // order, so `f` has to be associative but needn't be commutative.
+    pub fn par_reduce<I, F>(&self, items: I, f: F) -> Option<I::Item>
+    where
+        I: IntoIterator,
+        I::Item: Send,
+        F: Fn(I::Item, I::Item) -> I::Item + Sync,
+    {
+        let chunks = self.chunk(items);
+        let mut results: Vec<Option<I::Item>> = chunks.iter().map(|_| None).collect();
+        let f = &f;
+        self.scope(|s| {
+            for (chunk, result) in chunks.into_iter().zip(&mut results) {
+                s.execute(move || *result = chunk.into_iter().reduce(f));
+            }
+        });
+        results.into_iter().flatten().reduce(f)
+    }
+
+    fn chunk<I: IntoIterator>(&self, items: I) -> Vec<Vec<I::Item>> {
+        let mut items: Vec<I::Item> = items.into_iter().collect();
+        let count = (self.shared.max_threads * CHUNKS_PER_WORKER).min(items.len());
+        let mut chunks = Vec::with_capacity(count);
+        // Peel chunks off the end so each split_off only moves one chunk;
+        // the first `len % count` chunks get one extra item.
+        for i in (0..count).rev() {
+            let len = items.len() / (i + 1);
+            chunks.push(items.split_off(items.len() - len));
+        }
+        chunks.reverse();
+        chunks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pool::OverflowPolicy;
+
+    #[test]
+    fn parallel_helpers_keep_the_input_order() {
+        let pool = ThreadPool::new(3);
+        let words = ["a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k"];
+
+        let upper = pool.par_map(&words, |word| word.to_uppercase());
+        assert_eq!("ABCDEFGHIJK", upper.concat());
+        let joined = pool.par_reduce(words.iter().map(|word| word.to_string()), |a, b| a + &b);
+        assert_eq!(Some("abcdefghijk".to_string()), joined);
+        assert_eq!(None, pool.par_reduce(Vec::<u32>::new(), |a, b| a + b));
+
+        let total = std::sync::atomic::AtomicUsize::new(0);
+        pool.par_for_each(1..=1000, |n| {
+            total.fetch_add(n, std::sync::atomic::Ordering::SeqCst);
+        });
+        assert_eq!(500_500, total.into_inner());
+
+        let sizes: Vec<usize> = pool.chunk(0..14).iter().map(Vec::len).collect();
+        assert_eq!(12, sizes.len());
+        assert_eq!(14, sizes.iter().sum::<usize>());
+        assert_eq!(vec![2, 2], sizes[..2].to_vec());
+    }
+
+    #[test]
+    fn a_full_queue_loses_no_chunks() {
+        // Far more chunks than the queue holds; none of them may be refused.
+        let pool = ThreadPool::builder()
+            .num_threads(2)
+            .queue_capacity(1)
+            .overflow_policy(OverflowPolicy::Reject)
+            .build()
+            .unwrap();
+        let squares = pool.par_map(0..100u64, |n| n * n);
+        assert_eq!((0..100).map(|n| n * n).collect::<Vec<_>>(), squares);
+        assert_eq!(Some(4950), pool.par_reduce(0..100u64, |a, b| a + b));
+    }
+}
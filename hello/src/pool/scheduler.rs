@@ -0,0 +1,505 @@
+use super::{lock, Job};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+// What happens to a job submitted while the queue is at capacity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    // Wait until a worker takes a job off the queue.
+    #[default]
+    Block,
+    // Refuse the job; try_execute returns QueueFullError.
+    Reject,
+    // Throw away the oldest queued job to make room.
+    DropOldest,
+    // Run the job right away on the thread that submitted it.
+    CallerRuns,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueFullError;
+
+impl fmt::Display for QueueFullError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "thread pool queue is full")
+    }
+}
+
+impl Error for QueueFullError {}
+
+// Workers always take the job with the highest priority first. A job that
+// keeps losing to newer, more urgent ones gains a level for every aging
+// interval it has waited, so low-priority work can't starve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Priority {
+    const LEVELS: usize = 3;
+}
+
+// Every worker owns a local deque, and jobs submitted from outside the pool
+// go into a shared injector queue. A worker looks for work in its own deque
+// first (newest job first, which keeps related work on one thread), then in
+// the injector, and finally steals the oldest job from another worker.
+// Only Normal jobs go onto the local deques; anything else goes through
+// the injector, where jobs ranked above Normal are taken before the local
+// deque is even looked at.
+// Apart from the brief queue locks, nothing is shared on the fast path;
+// the single sleep mutex is only touched when workers are actually idle.
+pub(super) struct Scheduler {
+    injector: Mutex<Injector>,
+    // Jobs in the injector, so that workers busy with their own deques can
+    // skip its lock when it's empty.
+    injected: AtomicUsize,
+    aging: Option<Duration>,
+    // Only the injector is bounded. Jobs that workers submit go to their
+    // own deques, because blocking a worker on its own queue could
+    // deadlock the pool.
+    capacity: Option<usize>,
+    policy: OverflowPolicy,
+    space: Condvar,
+    locals: Vec<Mutex<VecDeque<Queued>>>,
+    // Number of queued jobs. It is bumped before a job becomes visible, so
+    // it may briefly run ahead of the queues but never behind them.
+    pending: AtomicUsize,
+    sleepers: AtomicUsize,
+    sleep: Mutex<()>,
+    wake: Condvar,
+    terminating: AtomicBool,
+}
+
+// What a worker gets back from Scheduler::wait.
+pub(super) enum Wait {
+    Job(Queued),
+    // Nothing to do for the whole idle timeout.
+    Idle,
+    // The pool is shutting down and the queues are empty.
+    Terminate,
+}
+
+impl Scheduler {
+    pub(super) fn new(
+        workers: usize,
+        capacity: Option<usize>,
+        policy: OverflowPolicy,
+        aging: Option<Duration>,
+    ) -> Scheduler {
+        Scheduler {
+            injector: Mutex::new(Injector::new()),
+            injected: AtomicUsize::new(0),
+            aging,
+            capacity,
+            policy,
+            space: Condvar::new(),
+            locals: (0..workers).map(|_| Mutex::new(VecDeque::new())).collect(),
+            pending: AtomicUsize::new(0),
+            sleepers: AtomicUsize::new(0),
+            sleep: Mutex::new(()),
+            wake: Condvar::new(),
+            terminating: AtomicBool::new(false),
+        }
+    }
+
+    pub(super) fn policy(&self) -> OverflowPolicy {
+        self.policy
+    }
+
+    pub(super) fn pending(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    // Workers currently parked waiting for a job.
+    pub(super) fn idle(&self) -> usize {
+        self.sleepers.load(Ordering::SeqCst)
+    }
+
+    // `worker` is the id of the calling worker thread, if the job was
+    // submitted from inside the pool. The job is handed back if the queue
+    // is full and the policy is Reject or CallerRuns. Under DropOldest, the
+    // job that was thrown out to make room is returned instead; the caller
+    // should drop it outside of any locks, since it may own a connection.
+    pub(super) fn push(
+        &self,
+        job: Job,
+        priority: Priority,
+        worker: Option<usize>,
+    ) -> Result<Option<Job>, Job> {
+        let mut dropped = None;
+        let job = Queued {
+            job,
+            queued_at: Instant::now(),
+            droppable: true,
+        };
+        match worker {
+            Some(id) if priority == Priority::Normal => {
+                self.pending.fetch_add(1, Ordering::SeqCst);
+                lock(&self.locals[id]).push_back(job);
+            }
+            _ => {
+                let mut injector = lock(&self.injector);
+                // Workers never wait for room; see `capacity`.
+                while worker.is_none()
+                    && self
+                        .capacity
+                        .is_some_and(|capacity| injector.len >= capacity)
+                {
+                    match self.policy {
+                        OverflowPolicy::Block => {
+                            injector = self
+                                .space
+                                .wait(injector)
+                                .unwrap_or_else(PoisonError::into_inner);
+                        }
+                        OverflowPolicy::Reject | OverflowPolicy::CallerRuns => return Err(job.job),
+                        OverflowPolicy::DropOldest => match injector.pop_least_urgent() {
+                            Some(oldest) => {
+                                dropped = Some(oldest);
+                                self.injected.fetch_sub(1, Ordering::SeqCst);
+                                self.pending.fetch_sub(1, Ordering::SeqCst);
+                            }
+                            // Nothing that may be thrown out (the builder
+                            // rules out a capacity of 0, and accepted work
+                            // is kept), so go over capacity rather than spin.
+                            None => break,
+                        },
+                    }
+                }
+                self.pending.fetch_add(1, Ordering::SeqCst);
+                self.injected.fetch_add(1, Ordering::SeqCst);
+                injector.push(job, priority);
+            }
+        }
+        self.wake_one();
+        Ok(dropped)
+    }
+
+    // Queues a job regardless of the capacity. For work the pool has
+    // already accepted, such as a woken task, which must not be lost.
+    pub(super) fn push_unbounded(&self, job: Job) {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        self.injected.fetch_add(1, Ordering::SeqCst);
+        lock(&self.injector).push(
+            Queued {
+                job,
+                queued_at: Instant::now(),
+                droppable: false,
+            },
+            Priority::Normal,
+        );
+        self.wake_one();
+    }
+
+    fn wake_one(&self) {
+        // Pairs with the check in wait(): either the sleeper sees the new
+        // pending count, or we see the sleeper and wake it.
+        if self.sleepers.load(Ordering::SeqCst) > 0 {
+            let _sleep = lock(&self.sleep);
+            self.wake.notify_one();
+        }
+    }
+
+    pub(super) fn pop(&self, id: usize) -> Option<Queued> {
+        // One lock at a time: holding our own deque while stealing from
+        // another worker's could deadlock with that worker doing the same.
+        let mut job = self.pop_injector(Priority::High);
+        if job.is_none() {
+            job = lock(&self.locals[id]).pop_back();
+        }
+        if job.is_none() {
+            job = self.pop_injector(Priority::Low);
+        }
+        let job = job.or_else(|| self.steal(id))?;
+        self.pending.fetch_sub(1, Ordering::SeqCst);
+        Some(job)
+    }
+
+    // Takes the most urgent injector job, if it ranks at least `at_least`
+    // once aging is taken into account.
+    fn pop_injector(&self, at_least: Priority) -> Option<Queued> {
+        if self.injected.load(Ordering::SeqCst) == 0 {
+            return None;
+        }
+        let mut injector = lock(&self.injector);
+        let job = injector.pop_most_urgent(Instant::now(), self.aging, at_least as u64)?;
+        self.injected.fetch_sub(1, Ordering::SeqCst);
+        if self.capacity.is_some() {
+            self.space.notify_one();
+        }
+        Some(job)
+    }
+
+    fn steal(&self, id: usize) -> Option<Queued> {
+        let count = self.locals.len();
+        (1..count)
+            .map(|offset| (id + offset) % count)
+            .find_map(|victim| lock(&self.locals[victim]).pop_front())
+    }
+
+    // Blocks until there is a job for the worker, the pool is terminating
+    // and every queued job has been taken, or `idle_timeout` passes
+    // without any work showing up.
+    pub(super) fn wait(&self, id: usize, idle_timeout: Option<Duration>) -> Wait {
+        let deadline = idle_timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            if let Some(job) = self.pop(id) {
+                return Wait::Job(job);
+            }
+
+            let sleep = lock(&self.sleep);
+            self.sleepers.fetch_add(1, Ordering::SeqCst);
+            let mut outcome = None;
+            if self.pending.load(Ordering::SeqCst) == 0 {
+                if self.terminating.load(Ordering::SeqCst) {
+                    outcome = Some(Wait::Terminate);
+                } else {
+                    match deadline {
+                        None => {
+                            drop(
+                                self.wake
+                                    .wait(sleep)
+                                    .unwrap_or_else(PoisonError::into_inner),
+                            );
+                        }
+                        Some(deadline) => {
+                            let now = Instant::now();
+                            if now >= deadline {
+                                outcome = Some(Wait::Idle);
+                            } else {
+                                drop(
+                                    self.wake
+                                        .wait_timeout(sleep, deadline - now)
+                                        .unwrap_or_else(PoisonError::into_inner),
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+            self.sleepers.fetch_sub(1, Ordering::SeqCst);
+            if let Some(outcome) = outcome {
+                return outcome;
+            }
+        }
+    }
+
+    // Workers finish whatever is still queued and then exit.
+    pub(super) fn terminate(&self) {
+        self.terminating.store(true, Ordering::SeqCst);
+        let _sleep = lock(&self.sleep);
+        self.wake.notify_all();
+    }
+}
+
+pub(super) struct Queued {
+    pub(super) job: Job,
+    pub(super) queued_at: Instant,
+    // Whether DropOldest may throw it away; work the pool has already
+    // accepted, like a woken task, may not be.
+    pub(super) droppable: bool,
+}
+
+// One FIFO per priority level. Within a level the front job is always the
+// one that has waited longest, so it is also the one that has aged the
+// most, and comparing the fronts is enough to find the most urgent job.
+struct Injector {
+    levels: [VecDeque<Queued>; Priority::LEVELS],
+    len: usize,
+}
+
+impl Injector {
+    fn new() -> Injector {
+        Injector {
+            levels: Default::default(),
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, job: Queued, priority: Priority) {
+        self.levels[priority as usize].push_back(job);
+        self.len += 1;
+    }
+
+    // Ties go to the job that was queued first.
+    fn pop_most_urgent(
+        &mut self,
+        now: Instant,
+        aging: Option<Duration>,
+        at_least: u64,
+    ) -> Option<Queued> {
+        let (level, _) = self
+            .levels
+            .iter()
+            .enumerate()
+            .filter_map(|(level, queue)| {
+                let front = queue.front()?;
+                let waited = now.saturating_duration_since(front.queued_at);
+                let aged = aging.map_or(0, |aging| (waited.as_nanos() / aging.as_nanos()) as u64);
+                Some((level, (level as u64 + aged, front.queued_at)))
+            })
+            .filter(|(_, (rank, _))| *rank >= at_least)
+            .max_by(|(_, (rank_a, at_a)), (_, (rank_b, at_b))| {
+                rank_a.cmp(rank_b).then(at_b.cmp(at_a))
+            })?;
+        self.len -= 1;
+        self.levels[level].pop_front()
+    }
+
+    // What DropOldest throws away: the oldest droppable job of the lowest
+    // level.
+    fn pop_least_urgent(&mut self) -> Option<Job> {
+        let queued = self.levels.iter_mut().find_map(|queue| {
+            let index = queue.iter().position(|queued| queued.droppable)?;
+            queue.remove(index)
+        })?;
+        self.len -= 1;
+        Some(queued.job)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+
+    // A job that reports `id` once it runs, so tests can tell jobs apart.
+    fn job(ran: &mpsc::Sender<u32>, id: u32) -> Job {
+        let ran = ran.clone();
+        Box::new(move || ran.send(id).unwrap())
+    }
+
+    fn accept(
+        scheduler: &Scheduler,
+        job: Job,
+        priority: Priority,
+        worker: Option<usize>,
+    ) -> Option<Job> {
+        scheduler
+            .push(job, priority, worker)
+            .unwrap_or_else(|_| panic!("job refused"))
+    }
+
+    fn run(job: Job, ran: &mpsc::Receiver<u32>) -> u32 {
+        job();
+        ran.try_recv().unwrap()
+    }
+
+    fn queued(ran: &mpsc::Sender<u32>, id: u32, queued_at: Instant) -> Queued {
+        Queued {
+            job: job(ran, id),
+            queued_at,
+            droppable: true,
+        }
+    }
+
+    #[test]
+    fn workers_take_their_newest_job_then_steal_the_oldest() {
+        let (sender, ran) = mpsc::channel();
+        let scheduler = Scheduler::new(2, None, OverflowPolicy::Block, None);
+        for (id, worker) in [
+            (1, Some(0)),
+            (2, Some(0)),
+            (3, Some(1)),
+            (4, Some(1)),
+            (5, None),
+        ] {
+            accept(&scheduler, job(&sender, id), Priority::Normal, worker);
+        }
+
+        let mut order = Vec::new();
+        while let Some(queued) = scheduler.pop(0) {
+            order.push(run(queued.job, &ran));
+        }
+        assert_eq!(vec![2, 1, 5, 3, 4], order);
+        assert_eq!(0, scheduler.pending());
+    }
+
+    #[test]
+    fn waiting_jobs_age_past_more_urgent_ones() {
+        let (sender, ran) = mpsc::channel();
+        let now = Instant::now();
+        let mut injector = Injector::new();
+        injector.push(
+            queued(&sender, 1, now - Duration::from_secs(3)),
+            Priority::Low,
+        );
+        injector.push(queued(&sender, 2, now), Priority::High);
+
+        // Without aging, priority alone decides.
+        let first = injector.pop_most_urgent(now, None, 0).unwrap();
+        assert_eq!(2, run(first.job, &ran));
+        injector.push(queued(&sender, 2, now), Priority::High);
+
+        // Three intervals lift Low above High.
+        let aging = Some(Duration::from_secs(1));
+        let first = injector.pop_most_urgent(now, aging, 0).unwrap();
+        assert_eq!(1, run(first.job, &ran));
+
+        // Jobs below `at_least` are left alone.
+        injector.push(queued(&sender, 3, now), Priority::Normal);
+        let first = injector
+            .pop_most_urgent(now, aging, Priority::High as u64)
+            .unwrap();
+        assert_eq!(2, run(first.job, &ran));
+        assert!(injector
+            .pop_most_urgent(now, aging, Priority::High as u64)
+            .is_none());
+        assert_eq!(1, injector.len);
+    }
+
+    #[test]
+    fn drop_oldest_evicts_from_the_lowest_level() {
+        let (sender, ran) = mpsc::channel();
+        let scheduler = Scheduler::new(1, Some(3), OverflowPolicy::DropOldest, None);
+        scheduler.push_unbounded(job(&sender, 1));
+        accept(&scheduler, job(&sender, 2), Priority::Low, None);
+        accept(&scheduler, job(&sender, 3), Priority::Low, None);
+
+        let dropped = accept(&scheduler, job(&sender, 4), Priority::High, None);
+        assert_eq!(2, run(dropped.unwrap(), &ran));
+        let dropped = accept(&scheduler, job(&sender, 5), Priority::Normal, None);
+        assert_eq!(3, run(dropped.unwrap(), &ran));
+        // Job 1 sits first at Normal but was already accepted, so job 5
+        // goes instead.
+        let dropped = accept(&scheduler, job(&sender, 6), Priority::Normal, None);
+        assert_eq!(5, run(dropped.unwrap(), &ran));
+
+        let mut order = Vec::new();
+        while let Some(queued) = scheduler.pop(0) {
+            order.push(run(queued.job, &ran));
+        }
+        assert_eq!(vec![4, 1, 6], order);
+    }
+
+    #[test]
+    fn blocked_producers_wake_when_a_job_is_taken() {
+        let (sender, ran) = mpsc::channel();
+        let scheduler = Arc::new(Scheduler::new(1, Some(1), OverflowPolicy::Block, None));
+        accept(&scheduler, job(&sender, 1), Priority::Normal, None);
+
+        let (pushed, was_pushed) = mpsc::channel();
+        let producer = {
+            let scheduler = Arc::clone(&scheduler);
+            let job = job(&sender, 2);
+            thread::spawn(move || {
+                accept(&scheduler, job, Priority::Normal, None);
+                pushed.send(()).unwrap();
+            })
+        };
+        assert!(was_pushed.recv_timeout(Duration::from_millis(100)).is_err());
+
+        assert_eq!(1, run(scheduler.pop(0).unwrap().job, &ran));
+        was_pushed.recv_timeout(Duration::from_secs(5)).unwrap();
+        producer.join().unwrap();
+        assert_eq!(2, run(scheduler.pop(0).unwrap().job, &ran));
+    }
+}
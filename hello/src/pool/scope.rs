@@ -0,0 +1,148 @@
+use super::{lock, Job, Shared};
+use std::marker::PhantomData;
+use std::mem;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::Duration;
+
+// Lets jobs borrow from the stack of the thread that called
+// ThreadPool::scope, in the spirit of std::thread::scope. The lifetimes
+// work the same way: 'env is what the jobs may borrow, 'scope is the
+// lifetime of the scope itself.
+pub struct Scope<'scope, 'env: 'scope> {
+    shared: Arc<Shared>,
+    state: Arc<ScopeState>,
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+struct ScopeState {
+    // Jobs submitted through the scope that haven't finished or been
+    // dropped yet.
+    pending: Mutex<usize>,
+    done: Condvar,
+    panicked: AtomicBool,
+}
+
+pub(super) fn scope<'env, F, T>(shared: &Arc<Shared>, f: F) -> T
+where
+    F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+{
+    let scope = Scope {
+        shared: Arc::clone(shared),
+        state: Arc::new(ScopeState {
+            pending: Mutex::new(0),
+            done: Condvar::new(),
+            panicked: AtomicBool::new(false),
+        }),
+        scope: PhantomData,
+        env: PhantomData,
+    };
+    // Even if `f` panics, the jobs it submitted still borrow from the
+    // caller's stack, so wait for them before unwinding any further.
+    let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+    scope.wait();
+    match result {
+        Ok(value) => {
+            if scope.state.panicked.load(Ordering::SeqCst) {
+                panic!("a scoped job panicked");
+            }
+            value
+        }
+        Err(payload) => panic::resume_unwind(payload),
+    }
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    // Runs the job on the pool. It may borrow anything that outlives the
+    // scope. If the job panics, the panic goes to the pool's panic handler
+    // as usual, and `scope` panics once all the other jobs are done.
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'scope,
+    {
+        *lock(&self.state.pending) += 1;
+        let job = ScopedJob {
+            f: Some(Box::new(f)),
+            state: Arc::clone(&self.state),
+        };
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || job.run());
+        // SAFETY: the job only outlives 'scope on paper. scope() doesn't
+        // return until `pending` is back to zero, and a ScopedJob only
+        // decrements it after its closure has been run or dropped.
+        let job: Job = unsafe { mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Job>(job) };
+        // scope() promises that every job runs, so the queue's capacity and
+        // overflow policy don't apply; the caller is about to wait for these
+        // jobs anyway, which bounds how many can pile up.
+        self.shared.submit_unbounded(job);
+    }
+
+    fn wait(&self) {
+        let worker = self.shared.current_worker();
+        let mut pending = lock(&self.state.pending);
+        while *pending > 0 {
+            match worker {
+                // Waiting on a worker thread ties up one of the workers our
+                // jobs need, so lend a hand instead of sleeping. The job we
+                // pick up may not be ours, in which case it simply runs a
+                // little earlier than it otherwise would have.
+                Some(id) => {
+                    drop(pending);
+                    if let Some(job) = self.shared.scheduler.pop(id) {
+                        self.shared.run_job(Some(id), job);
+                        pending = lock(&self.state.pending);
+                    } else {
+                        pending = lock(&self.state.pending);
+                        if *pending > 0 {
+                            pending = self
+                                .state
+                                .done
+                                .wait_timeout(pending, Duration::from_millis(1))
+                                .unwrap_or_else(PoisonError::into_inner)
+                                .0;
+                        }
+                    }
+                }
+                None => {
+                    pending = self
+                        .state
+                        .done
+                        .wait(pending)
+                        .unwrap_or_else(PoisonError::into_inner);
+                }
+            }
+        }
+    }
+}
+
+struct ScopedJob<'scope> {
+    f: Option<Box<dyn FnOnce() + Send + 'scope>>,
+    state: Arc<ScopeState>,
+}
+
+impl ScopedJob<'_> {
+    fn run(mut self) {
+        let f = self.f.take().unwrap();
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(f)) {
+            self.state.panicked.store(true, Ordering::SeqCst);
+            // Count the job as finished before the worker reports the panic.
+            drop(self);
+            panic::resume_unwind(payload);
+        }
+    }
+}
+
+impl Drop for ScopedJob<'_> {
+    fn drop(&mut self) {
+        // The closure and everything it borrows must be gone before the
+        // scope is allowed to end.
+        drop(self.f.take());
+        let mut pending = lock(&self.state.pending);
+        *pending -= 1;
+        if *pending == 0 {
+            self.state.done.notify_all();
+        }
+    }
+}
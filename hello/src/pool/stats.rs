@@ -0,0 +1,162 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+// A point-in-time view of the pool, from ThreadPool::stats. The counters are
+// read one after another while the pool keeps running, so they are only
+// roughly consistent with each other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolStats {
+    // Worker threads currently running.
+    pub workers: usize,
+    // Workers that are in the middle of a job.
+    pub active_workers: usize,
+    pub queued_jobs: usize,
+    // Jobs that ran to the end without panicking.
+    pub completed_jobs: u64,
+    pub panicked_jobs: u64,
+    // Jobs refused or thrown out because the queue was full.
+    pub dropped_jobs: u64,
+    // How long jobs waited in the queue before a worker picked them up.
+    pub queue_latency: Histogram,
+    // How long jobs took to run.
+    pub run_time: Histogram,
+}
+
+// Bucket 0 counts durations under 1µs, bucket i those under 2^i µs, and the
+// last bucket everything from about 67 seconds up.
+const BUCKETS: usize = 28;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    counts: [u64; BUCKETS],
+    total: Duration,
+}
+
+impl Histogram {
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count() {
+            0 => None,
+            count => Some(Duration::from_nanos(
+                (self.total.as_nanos() / u128::from(count)) as u64,
+            )),
+        }
+    }
+
+    // An upper bound for the given percentile (0 to 100): the top of the
+    // bucket it falls into. None if nothing has been recorded.
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((percentile.clamp(0.0, 100.0) / 100.0 * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        self.buckets().find_map(|(bound, n)| {
+            seen += n;
+            (seen >= rank).then_some(bound)
+        })
+    }
+
+    // (upper bound, count) for every bucket, shortest first. The last
+    // bucket is open-ended and reports Duration::MAX as its bound.
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .map(|(i, &count)| (upper_bound(i), count))
+    }
+}
+
+fn upper_bound(bucket: usize) -> Duration {
+    if bucket == BUCKETS - 1 {
+        Duration::MAX
+    } else {
+        Duration::from_micros(1 << bucket)
+    }
+}
+
+fn bucket(duration: Duration) -> usize {
+    let micros = duration.as_micros();
+    let bits = (u128::BITS - micros.leading_zeros()) as usize;
+    bits.min(BUCKETS - 1)
+}
+
+// The live counters behind PoolStats. Workers only ever do relaxed atomic
+// adds here, so keeping them up to date costs next to nothing.
+pub(super) struct Metrics {
+    pub(super) active: AtomicUsize,
+    pub(super) completed: AtomicU64,
+    pub(super) panicked: AtomicU64,
+    pub(super) dropped: AtomicU64,
+    pub(super) queue_latency: Recorder,
+    pub(super) run_time: Recorder,
+}
+
+impl Metrics {
+    pub(super) fn new() -> Metrics {
+        Metrics {
+            active: AtomicUsize::new(0),
+            completed: AtomicU64::new(0),
+            panicked: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            queue_latency: Recorder::new(),
+            run_time: Recorder::new(),
+        }
+    }
+}
+
+pub(super) struct Recorder {
+    counts: [AtomicU64; BUCKETS],
+    total_nanos: AtomicU64,
+}
+
+impl Recorder {
+    fn new() -> Recorder {
+        Recorder {
+            counts: std::array::from_fn(|_| AtomicU64::new(0)),
+            total_nanos: AtomicU64::new(0),
+        }
+    }
+
+    pub(super) fn record(&self, duration: Duration) {
+        self.counts[bucket(duration)].fetch_add(1, Ordering::Relaxed);
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.total_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    pub(super) fn snapshot(&self) -> Histogram {
+        Histogram {
+            counts: std::array::from_fn(|i| self.counts[i].load(Ordering::Relaxed)),
+            total: Duration::from_nanos(self.total_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_and_percentiles() {
+        let recorder = Recorder::new();
+        for micros in [0, 1, 3, 3, 900, 5_000_000_000] {
+            recorder.record(Duration::from_micros(micros));
+        }
+        let histogram = recorder.snapshot();
+
+        assert_eq!(6, histogram.count());
+        assert_eq!(Some(Duration::from_micros(1)), histogram.percentile(10.0));
+        assert_eq!(Some(Duration::from_micros(4)), histogram.percentile(50.0));
+        assert_eq!(
+            Some(Duration::from_micros(1024)),
+            histogram.percentile(80.0)
+        );
+        assert_eq!(Some(Duration::MAX), histogram.percentile(100.0));
+        let counts: Vec<u64> = histogram.buckets().map(|(_, n)| n).collect();
+        assert_eq!(&[1, 1, 2, 0], &counts[..4]);
+    }
+}
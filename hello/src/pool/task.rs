@@ -0,0 +1,215 @@
+use super::join_handle::{self, JoinError, JoinHandle};
+use super::{lock, Priority, QueueFullError, Shared, ThreadPool};
+use std::future::{self, Future};
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+
+// Task states. A task is polled by at most one worker at a time; a wakeup
+// that arrives while it's being polled is remembered and the task is
+// queued again afterwards.
+const IDLE: u8 = 0;
+const SCHEDULED: u8 = 1;
+const RUNNING: u8 = 2;
+const NOTIFIED: u8 = 3;
+const DONE: u8 = 4;
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+struct Task {
+    future: Mutex<Option<BoxFuture>>,
+    state: AtomicU8,
+    shared: Arc<Shared>,
+}
+
+impl ThreadPool {
+    // Runs a future on the pool. Each time it's woken, it is polled as a job
+    // on one of the workers, so a future that is waiting on a socket or a
+    // timer doesn't hold up a thread. Wakeups skip the queue capacity: the
+    // task has already been accepted, and dropping it halfway would be worse.
+    pub fn spawn_future<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (task, handle) = self.task(future);
+        task.submit();
+        handle
+    }
+
+    // Like spawn_future, but the first submission goes through the queue
+    // capacity and overflow policy as try_execute does, so a pool that is
+    // full can turn new tasks away. Once accepted, the task's wakeups are
+    // never refused. A refused future is dropped without being polled.
+    pub fn try_spawn_future<F>(&self, future: F) -> Result<JoinHandle<F::Output>, QueueFullError>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (task, handle) = self.task(future);
+        let job = Box::new(move || task.run());
+        self.shared
+            .submit(job, Priority::Normal, self.shared.current_worker())?;
+        Ok(handle)
+    }
+
+    fn task<F>(&self, future: F) -> (Arc<Task>, JoinHandle<F::Output>)
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        let mut future = Box::pin(future);
+        let future = future::poll_fn(move |cx| {
+            match panic::catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(cx))) {
+                Ok(Poll::Ready(value)) => {
+                    let _ = sender.send(Ok(value));
+                    Poll::Ready(())
+                }
+                Ok(Poll::Pending) => Poll::Pending,
+                // Like spawn: report it through the handle and to the pool.
+                Err(payload) => {
+                    let message = join_handle::panic_message(payload.as_ref());
+                    let _ = sender.send(Err(JoinError::Panicked(message)));
+                    panic::resume_unwind(payload);
+                }
+            }
+        });
+        let task = Arc::new(Task {
+            future: Mutex::new(Some(Box::pin(future))),
+            state: AtomicU8::new(SCHEDULED),
+            shared: Arc::clone(&self.shared),
+        });
+        (task, JoinHandle::new(receiver))
+    }
+}
+
+impl Task {
+    fn submit(self: Arc<Self>) {
+        let shared = Arc::clone(&self.shared);
+        shared.submit_unbounded(Box::new(move || self.run()));
+    }
+
+    fn run(self: Arc<Self>) {
+        self.state.store(RUNNING, Ordering::SeqCst);
+        // Take the future out so a panic while polling just drops it.
+        let Some(mut future) = lock(&self.future).take() else {
+            return;
+        };
+        let waker = Waker::from(Arc::clone(&self));
+        if future
+            .as_mut()
+            .poll(&mut Context::from_waker(&waker))
+            .is_ready()
+        {
+            self.state.store(DONE, Ordering::SeqCst);
+            return;
+        }
+        *lock(&self.future) = Some(future);
+        if self
+            .state
+            .compare_exchange(RUNNING, IDLE, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            // Woken while we were polling it.
+            self.state.store(SCHEDULED, Ordering::SeqCst);
+            self.submit();
+        }
+    }
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        let mut state = self.state.load(Ordering::SeqCst);
+        loop {
+            let next = match state {
+                IDLE => SCHEDULED,
+                RUNNING => NOTIFIED,
+                _ => return,
+            };
+            match self
+                .state
+                .compare_exchange(state, next, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) if next == SCHEDULED => return self.submit(),
+                Ok(_) => return,
+                Err(actual) => state = actual,
+            }
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::pool::OverflowPolicy;
+    use crate::runtime;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn futures_wait_without_holding_a_worker() {
+        let pool = ThreadPool::new(1);
+        let start = Instant::now();
+        let handles: Vec<_> = (0..20u64)
+            .map(|i| {
+                pool.spawn_future(async move {
+                    runtime::sleep(Duration::from_millis(100)).await;
+                    i
+                })
+            })
+            .collect();
+        let sum: u64 = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .sum();
+        assert_eq!(190, sum);
+        // With a blocking sleep these would take two seconds on one worker.
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        pool.set_panic_handler(|_, _| {});
+        let failed = pool.spawn_future(async {
+            runtime::sleep(Duration::from_millis(1)).await;
+            panic!("async panic");
+        });
+        assert_eq!(
+            Err(JoinError::Panicked("async panic".to_string())),
+            failed.join()
+        );
+    }
+
+    #[test]
+    fn only_new_futures_are_refused_when_full() {
+        let pool = ThreadPool::builder()
+            .num_threads(1)
+            .queue_capacity(1)
+            .overflow_policy(OverflowPolicy::Reject)
+            .build()
+            .unwrap();
+        let sleeper = pool
+            .try_spawn_future(async {
+                runtime::sleep(Duration::from_millis(50)).await;
+                "woke"
+            })
+            .unwrap();
+        thread::sleep(Duration::from_millis(10));
+
+        // Hold the only worker and fill the queue; the sleeper's wakeup
+        // still has to get in.
+        let (release, blocked) = mpsc::channel::<()>();
+        pool.execute(move || {
+            let _ = blocked.recv();
+        });
+        thread::sleep(Duration::from_millis(10));
+        let queued = pool.try_spawn_future(async { "queued" }).unwrap();
+        assert!(pool.try_spawn_future(async { "refused" }).is_err());
+        thread::sleep(Duration::from_millis(80));
+
+        drop(release);
+        assert_eq!(Ok("woke"), sleeper.join());
+        assert_eq!(Ok("queued"), queued.join());
+    }
+}
@@ -0,0 +1,186 @@
+use super::{lock, Job, Priority, Shared};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::io;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Delays are capped at this, since a long enough one (Duration::MAX, say)
+// can't be added to an Instant. A job this far out will never run anyway.
+const MAX_DELAY: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+// Returned by execute_after and execute_every. Dropping the handle leaves
+// the job scheduled; call cancel() to stop it.
+#[derive(Debug, Clone)]
+pub struct ScheduledHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl ScheduledHandle {
+    // A delayed job that hasn't been handed to a worker yet won't run, and
+    // a periodic job won't run again. A run already in progress finishes.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, atomic::Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(atomic::Ordering::SeqCst)
+    }
+}
+
+pub(super) enum Task {
+    Once(Job),
+    Every(Duration, Arc<dyn Fn() + Send + Sync + 'static>),
+}
+
+struct Entry {
+    due: Instant,
+    // Breaks ties so that jobs due at the same instant run in order.
+    seq: u64,
+    task: Task,
+    cancelled: Arc<AtomicBool>,
+}
+
+// BinaryHeap is a max-heap, so the ordering is reversed: the entry that is
+// due first compares greatest.
+impl Ord for Entry {
+    fn cmp(&self, other: &Entry) -> Ordering {
+        other
+            .due
+            .cmp(&self.due)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Entry) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Entry) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+#[derive(Default)]
+struct Queue {
+    entries: BinaryHeap<Entry>,
+    next_seq: u64,
+    stopped: bool,
+}
+
+// One thread that sleeps until the next entry is due and then hands the
+// job to the pool. It is started with the pool, so scheduling a job can't
+// fail.
+pub(super) struct Timer {
+    queue: Arc<(Mutex<Queue>, Condvar)>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Timer {
+    pub(super) fn new(shared: Arc<Shared>) -> io::Result<Timer> {
+        let queue = Arc::new((Mutex::new(Queue::default()), Condvar::new()));
+        let thread = {
+            let queue = Arc::clone(&queue);
+            thread::Builder::new()
+                .name(String::from("pool-timer"))
+                .spawn(move || run(&queue, &shared))?
+        };
+        Ok(Timer {
+            queue,
+            thread: Some(thread),
+        })
+    }
+
+    pub(super) fn schedule(&self, delay: Duration, task: Task) -> ScheduledHandle {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let due = Instant::now() + delay.min(MAX_DELAY);
+        let (queue, changed) = &*self.queue;
+        let mut queue = lock(queue);
+        let seq = queue.next_seq;
+        queue.next_seq += 1;
+        queue.entries.push(Entry {
+            due,
+            seq,
+            task,
+            cancelled: Arc::clone(&cancelled),
+        });
+        changed.notify_one();
+        ScheduledHandle { cancelled }
+    }
+
+    // Jobs that are still waiting for their time are dropped without
+    // running. Safe to call more than once.
+    pub(super) fn stop(&mut self) {
+        let (queue, changed) = &*self.queue;
+        lock(queue).stopped = true;
+        changed.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn run(queue: &(Mutex<Queue>, Condvar), shared: &Arc<Shared>) {
+    let (queue, changed) = queue;
+    let mut guard = lock(queue);
+    loop {
+        if guard.stopped {
+            return;
+        }
+        let now = Instant::now();
+        let due = match guard.entries.peek() {
+            None => {
+                guard = changed.wait(guard).unwrap_or_else(PoisonError::into_inner);
+                continue;
+            }
+            Some(entry) => entry.due,
+        };
+        if due > now {
+            guard = changed
+                .wait_timeout(guard, due - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+            continue;
+        }
+
+        let entry = guard.entries.pop().unwrap();
+        if entry.cancelled.load(atomic::Ordering::SeqCst) {
+            continue;
+        }
+        let job: Job = match entry.task {
+            Task::Once(job) => job,
+            Task::Every(interval, job) => {
+                // Keep a steady rate, but don't try to catch up on runs we
+                // missed while the pool was busy.
+                let seq = guard.next_seq;
+                guard.next_seq += 1;
+                guard.entries.push(Entry {
+                    due: (entry.due + interval.min(MAX_DELAY)).max(now),
+                    seq,
+                    task: Task::Every(interval, Arc::clone(&job)),
+                    cancelled: Arc::clone(&entry.cancelled),
+                });
+                Box::new(move || job())
+            }
+        };
+
+        // Submitting may block on a full queue; don't hold the timer lock.
+        drop(guard);
+        // A refused job is reported as Event::JobDropped.
+        let _ = shared.submit(job, Priority::Normal, None);
+        guard = lock(queue);
+    }
+}
@@ -0,0 +1,567 @@
+use crate::headers::Headers;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+
+// Upper bounds on what we're willing to buffer for a single request. The
+// request line and headers share one budget, the body has its own. Chunk
+// size lines count towards the body, and each may be at most
+// MAX_CHUNK_LINE long.
+pub const MAX_HEAD_SIZE: usize = 16 * 1024;
+pub(crate) const MAX_BODY_SIZE: usize = 8 * 1024 * 1024;
+pub(crate) const MAX_CHUNK_LINE: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Patch,
+    Options,
+    Trace,
+    Connect,
+}
+
+impl Method {
+    fn parse(token: &str) -> Option<Method> {
+        match token {
+            "GET" => Some(Method::Get),
+            "HEAD" => Some(Method::Head),
+            "POST" => Some(Method::Post),
+            "PUT" => Some(Method::Put),
+            "DELETE" => Some(Method::Delete),
+            "PATCH" => Some(Method::Patch),
+            "OPTIONS" => Some(Method::Options),
+            "TRACE" => Some(Method::Trace),
+            "CONNECT" => Some(Method::Connect),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Patch => "PATCH",
+            Method::Options => "OPTIONS",
+            Method::Trace => "TRACE",
+            Method::Connect => "CONNECT",
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Version {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    // The peer closed the connection before sending a single byte.
+    ConnectionClosed,
+    UnexpectedEof,
+    HeadTooLarge,
+    BodyTooLarge,
+    BadRequestLine,
+    UnknownMethod,
+    UnsupportedVersion,
+    BadTarget,
+    BadHeader,
+    BadContentLength,
+    BadTransferEncoding,
+    BadChunk,
+    Io(io::Error),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::ConnectionClosed => write!(f, "connection closed"),
+            ParseError::UnexpectedEof => write!(f, "unexpected end of request"),
+            ParseError::HeadTooLarge => write!(f, "request line and headers are too large"),
+            ParseError::BodyTooLarge => write!(f, "request body is too large"),
+            ParseError::BadRequestLine => write!(f, "malformed request line"),
+            ParseError::UnknownMethod => write!(f, "unknown request method"),
+            ParseError::UnsupportedVersion => write!(f, "unsupported HTTP version"),
+            ParseError::BadTarget => write!(f, "malformed request target"),
+            ParseError::BadHeader => write!(f, "malformed header"),
+            ParseError::BadContentLength => write!(f, "invalid Content-Length"),
+            ParseError::BadTransferEncoding => write!(f, "unsupported Transfer-Encoding"),
+            ParseError::BadChunk => write!(f, "malformed chunked body"),
+            ParseError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+impl From<io::Error> for ParseError {
+    fn from(e: io::Error) -> ParseError {
+        ParseError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: Method,
+    // The percent-decoded path, without the query string.
+    pub path: String,
+    pub query: Option<String>,
+    pub version: Version,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    // Filled in by the router from `:name` and `*name` pattern segments.
+    pub params: HashMap<String, String>,
+}
+
+impl Request {
+    // Reads exactly one request from the reader. Anything after the body is
+    // left in the reader, so a BufReader can be reused for the next request.
+    pub fn read_from<R: BufRead>(reader: &mut R) -> Result<Request, ParseError> {
+        Request::read_with_limit(reader, MAX_HEAD_SIZE)
+    }
+
+    // Like read_from, with a different cap on the request line and headers
+    // together; going over it is HeadTooLarge.
+    pub fn read_with_limit<R: BufRead>(
+        reader: &mut R,
+        max_head_size: usize,
+    ) -> Result<Request, ParseError> {
+        let mut budget = max_head_size;
+
+        // Be lenient about stray empty lines in front of the request line.
+        let request_line = loop {
+            match read_line(reader, &mut budget)? {
+                None => return Err(ParseError::ConnectionClosed),
+                Some(line) if line.is_empty() => continue,
+                Some(line) => break line,
+            }
+        };
+
+        let mut parts = request_line.split(' ');
+        let (method, target, version) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(target), Some(version)) if parts.next().is_none() => {
+                (method, target, version)
+            }
+            _ => return Err(ParseError::BadRequestLine),
+        };
+        let method = Method::parse(method).ok_or(ParseError::UnknownMethod)?;
+        let version = match version {
+            "HTTP/1.1" => Version::Http11,
+            "HTTP/1.0" => Version::Http10,
+            v if v.starts_with("HTTP/") => return Err(ParseError::UnsupportedVersion),
+            _ => return Err(ParseError::BadRequestLine),
+        };
+        let (path, query) = parse_target(target)?;
+
+        let mut headers = Headers::new();
+        read_headers(reader, &mut budget, &mut headers)?;
+
+        let body = read_body(reader, &mut budget, &mut headers)?;
+
+        Ok(Request {
+            method,
+            path,
+            query,
+            version,
+            headers,
+            body,
+            params: HashMap::new(),
+        })
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(|value| value.as_str())
+    }
+
+    // Returns the percent-decoded value of the first `name=value` pair in
+    // the query string.
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        self.query_pairs()
+            .into_iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn query_pairs(&self) -> Vec<(String, String)> {
+        let query = match &self.query {
+            Some(query) => query,
+            None => return Vec::new(),
+        };
+        query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (decode_query_component(key), decode_query_component(value))
+            })
+            .collect()
+    }
+}
+
+// Reads one CRLF (or bare LF) terminated line, charging it against the
+// remaining head budget. Returns None on a clean EOF before any byte.
+fn read_line<R: BufRead>(reader: &mut R, budget: &mut usize) -> Result<Option<String>, ParseError> {
+    let mut line = Vec::new();
+    let limit = *budget as u64 + 1;
+    let read = reader.by_ref().take(limit).read_until(b'\n', &mut line)?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if line.last() != Some(&b'\n') {
+        if read as u64 == limit {
+            return Err(ParseError::HeadTooLarge);
+        }
+        return Err(ParseError::UnexpectedEof);
+    }
+    *budget = budget.saturating_sub(read);
+
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| ParseError::BadHeader)
+}
+
+fn read_headers<R: BufRead>(
+    reader: &mut R,
+    budget: &mut usize,
+    headers: &mut Headers,
+) -> Result<(), ParseError> {
+    loop {
+        let line = read_line(reader, budget)?.ok_or(ParseError::UnexpectedEof)?;
+        if line.is_empty() {
+            return Ok(());
+        }
+        // Obsolete line folding is not allowed in requests.
+        if line.starts_with(' ') || line.starts_with('\t') {
+            return Err(ParseError::BadHeader);
+        }
+        let (name, value) = line.split_once(':').ok_or(ParseError::BadHeader)?;
+        if name.is_empty() || !name.bytes().all(is_token_char) {
+            return Err(ParseError::BadHeader);
+        }
+        headers.append(name, value.trim_matches(|c| c == ' ' || c == '\t'));
+    }
+}
+
+fn read_body<R: BufRead>(
+    reader: &mut R,
+    budget: &mut usize,
+    headers: &mut Headers,
+) -> Result<Vec<u8>, ParseError> {
+    let content_length = content_length(headers)?;
+
+    if let Some(encoding) = headers.get("Transfer-Encoding") {
+        // A request carrying both is a classic smuggling vector; refuse it.
+        if content_length.is_some() {
+            return Err(ParseError::BadTransferEncoding);
+        }
+        let last = encoding.rsplit(',').next().unwrap_or("").trim();
+        if !last.eq_ignore_ascii_case("chunked") {
+            return Err(ParseError::BadTransferEncoding);
+        }
+        return read_chunked(reader, budget, headers);
+    }
+
+    let length = content_length.unwrap_or(0);
+    if length > MAX_BODY_SIZE {
+        return Err(ParseError::BodyTooLarge);
+    }
+    let mut body = vec![0; length];
+    read_exact(reader, &mut body)?;
+    Ok(body)
+}
+
+fn content_length(headers: &Headers) -> Result<Option<usize>, ParseError> {
+    let mut length = None;
+    for value in headers.get_all("Content-Length") {
+        for part in value.split(',') {
+            let part = part.trim();
+            if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(ParseError::BadContentLength);
+            }
+            let parsed: usize = part.parse().map_err(|_| ParseError::BadContentLength)?;
+            match length {
+                Some(previous) if previous != parsed => return Err(ParseError::BadContentLength),
+                _ => length = Some(parsed),
+            }
+        }
+    }
+    Ok(length)
+}
+
+fn read_chunked<R: BufRead>(
+    reader: &mut R,
+    budget: &mut usize,
+    headers: &mut Headers,
+) -> Result<Vec<u8>, ParseError> {
+    let mut body = Vec::new();
+    // Everything read since the head, framing included.
+    let mut used = 0;
+    loop {
+        let left = MAX_BODY_SIZE.saturating_sub(used);
+        let mut line_budget = MAX_CHUNK_LINE.min(left);
+        let line = match read_line(reader, &mut line_budget) {
+            Ok(line) => line.ok_or(ParseError::UnexpectedEof)?,
+            Err(ParseError::HeadTooLarge) if left < MAX_CHUNK_LINE => {
+                return Err(ParseError::BodyTooLarge)
+            }
+            Err(ParseError::HeadTooLarge) => return Err(ParseError::BadChunk),
+            Err(e) => return Err(e),
+        };
+        used += MAX_CHUNK_LINE.min(left) - line_budget;
+        // Chunk extensions are allowed after a ';' and we ignore them.
+        let size = line.split(';').next().unwrap_or("").trim();
+        if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ParseError::BadChunk);
+        }
+        let size = usize::from_str_radix(size, 16).map_err(|_| ParseError::BadChunk)?;
+        if size == 0 {
+            break;
+        }
+        // Written so that a huge size can't overflow.
+        if size > MAX_BODY_SIZE.saturating_sub(used) {
+            return Err(ParseError::BodyTooLarge);
+        }
+        used += size + 2;
+
+        let start = body.len();
+        body.resize(start + size, 0);
+        read_exact(reader, &mut body[start..])?;
+
+        let mut crlf = [0; 2];
+        read_exact(reader, &mut crlf)?;
+        if &crlf != b"\r\n" {
+            return Err(ParseError::BadChunk);
+        }
+    }
+
+    // Trailer fields follow the last chunk and are merged into the headers.
+    read_headers(reader, budget, headers)?;
+    Ok(body)
+}
+
+// Running out of input is UnexpectedEof, so that the async server knows to
+// wait for more.
+fn read_exact<R: BufRead>(reader: &mut R, buf: &mut [u8]) -> Result<(), ParseError> {
+    reader.read_exact(buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => ParseError::UnexpectedEof,
+        _ => ParseError::Io(e),
+    })
+}
+
+fn parse_target(target: &str) -> Result<(String, Option<String>), ParseError> {
+    if !target.starts_with('/') {
+        return Err(ParseError::BadTarget);
+    }
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, Some(query.to_string())),
+        None => (target, None),
+    };
+    let path = percent_decode(path, false).ok_or(ParseError::BadTarget)?;
+    if path.contains('\0') {
+        return Err(ParseError::BadTarget);
+    }
+    Ok((path, query))
+}
+
+fn decode_query_component(component: &str) -> String {
+    percent_decode(component, true).unwrap_or_else(|| component.to_string())
+}
+
+fn percent_decode(input: &str, plus_as_space: bool) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = input.get(i + 1..i + 3)?;
+                // from_str_radix would also take a sign, as in "%+1".
+                if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return None;
+                }
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            b => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn is_token_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+
+    fn parse(raw: &str) -> Result<Request, ParseError> {
+        Request::read_from(&mut BufReader::new(raw.as_bytes()))
+    }
+
+    #[test]
+    fn parses_request_line_and_headers() {
+        let request =
+            parse("GET /a%20b?x=1&y=two+words HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        assert_eq!(Method::Get, request.method);
+        assert_eq!("/a b", request.path);
+        assert_eq!(Version::Http11, request.version);
+        assert_eq!(Some("localhost"), request.header("host"));
+        assert_eq!(Some("two words".to_string()), request.query_param("y"));
+        assert!(request.body.is_empty());
+    }
+
+    #[test]
+    fn reads_content_length_body() {
+        let request = parse("POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloextra").unwrap();
+        assert_eq!(b"hello", &request.body[..]);
+    }
+
+    #[test]
+    fn reads_chunked_body() {
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                   5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\nX-Trailer: yes\r\n\r\n";
+        let request = parse(raw).unwrap();
+        assert_eq!(b"hello world", &request.body[..]);
+        assert_eq!(Some("yes"), request.header("x-trailer"));
+
+        // Cut off between a chunk and its CRLF: incomplete, not malformed.
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello";
+        assert!(matches!(parse(raw), Err(ParseError::UnexpectedEof)));
+        // A size that would overflow the running total.
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                   1\r\na\r\nffffffffffffffff\r\n";
+        assert!(matches!(parse(raw), Err(ParseError::BodyTooLarge)));
+
+        // Many small chunks take nothing from a small head budget, but a
+        // size line may not go on forever.
+        let mut raw = String::from("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n");
+        for _ in 0..500 {
+            raw.push_str("1\r\na\r\n");
+        }
+        raw.push_str("0\r\n\r\n");
+        let mut reader = BufReader::new(raw.as_bytes());
+        let request = Request::read_with_limit(&mut reader, 256).unwrap();
+        assert_eq!(500, request.body.len());
+        let raw = format!(
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1;{}\r\na\r\n0\r\n\r\n",
+            "x".repeat(MAX_CHUNK_LINE)
+        );
+        assert!(matches!(parse(&raw), Err(ParseError::BadChunk)));
+    }
+
+    #[test]
+    fn leaves_pipelined_requests_in_the_reader() {
+        let raw = "GET /one HTTP/1.1\r\n\r\nGET /two HTTP/1.1\r\n\r\n";
+        let mut reader = BufReader::new(raw.as_bytes());
+        assert_eq!("/one", Request::read_from(&mut reader).unwrap().path);
+        assert_eq!("/two", Request::read_from(&mut reader).unwrap().path);
+        assert!(matches!(
+            Request::read_from(&mut reader),
+            Err(ParseError::ConnectionClosed)
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert!(matches!(
+            parse("GET /\r\n\r\n"),
+            Err(ParseError::BadRequestLine)
+        ));
+        assert!(matches!(
+            parse("FETCH / HTTP/1.1\r\n\r\n"),
+            Err(ParseError::UnknownMethod)
+        ));
+        assert!(matches!(
+            parse("GET / HTTP/2.0\r\n\r\n"),
+            Err(ParseError::UnsupportedVersion)
+        ));
+        assert!(matches!(
+            parse("GET x HTTP/1.1\r\n\r\n"),
+            Err(ParseError::BadTarget)
+        ));
+        for target in ["/%zz", "/%+1", "/%-1", "/%4"] {
+            let raw = format!("GET {} HTTP/1.1\r\n\r\n", target);
+            assert!(
+                matches!(parse(&raw), Err(ParseError::BadTarget)),
+                "{}",
+                target
+            );
+        }
+        assert!(matches!(
+            parse("GET / HTTP/1.1\r\nNo colon\r\n\r\n"),
+            Err(ParseError::BadHeader)
+        ));
+        assert!(matches!(
+            parse("POST / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n"),
+            Err(ParseError::BadContentLength)
+        ));
+        assert!(matches!(
+            parse("POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort"),
+            Err(ParseError::UnexpectedEof)
+        ));
+    }
+
+    #[test]
+    fn rejects_oversized_head() {
+        let raw = format!(
+            "GET / HTTP/1.1\r\nX-Big: {}\r\n\r\n",
+            "a".repeat(MAX_HEAD_SIZE)
+        );
+        assert!(matches!(parse(&raw), Err(ParseError::HeadTooLarge)));
+
+        let raw = format!("GET / HTTP/1.1\r\nX-Big: {}\r\n\r\n", "a".repeat(64));
+        let mut reader = BufReader::new(raw.as_bytes());
+        assert!(matches!(
+            Request::read_with_limit(&mut reader, 64),
+            Err(ParseError::HeadTooLarge)
+        ));
+    }
+}
@@ -0,0 +1,254 @@
+use crate::headers::Headers;
+use crate::http_date;
+use crate::log;
+use crate::log::Level;
+use crate::mime;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use std::time::SystemTime;
+
+// Sent in the Server header unless the handler set its own.
+const SERVER: &str = concat!("hello/", env!("CARGO_PKG_VERSION"));
+
+// The status codes we use, plus Other for anything else a handler needs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Created,
+    Accepted,
+    NoContent,
+    PartialContent,
+    MovedPermanently,
+    Found,
+    SeeOther,
+    NotModified,
+    TemporaryRedirect,
+    PermanentRedirect,
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    RequestTimeout,
+    PayloadTooLarge,
+    TooManyRequests,
+    RequestHeaderFieldsTooLarge,
+    InternalServerError,
+    NotImplemented,
+    ServiceUnavailable,
+    HttpVersionNotSupported,
+    Other(u16, &'static str),
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: Status,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl Status {
+    pub fn code(&self) -> u16 {
+        match self {
+            Status::Ok => 200,
+            Status::Created => 201,
+            Status::Accepted => 202,
+            Status::NoContent => 204,
+            Status::PartialContent => 206,
+            Status::MovedPermanently => 301,
+            Status::Found => 302,
+            Status::SeeOther => 303,
+            Status::NotModified => 304,
+            Status::TemporaryRedirect => 307,
+            Status::PermanentRedirect => 308,
+            Status::BadRequest => 400,
+            Status::Unauthorized => 401,
+            Status::Forbidden => 403,
+            Status::NotFound => 404,
+            Status::MethodNotAllowed => 405,
+            Status::RequestTimeout => 408,
+            Status::PayloadTooLarge => 413,
+            Status::TooManyRequests => 429,
+            Status::RequestHeaderFieldsTooLarge => 431,
+            Status::InternalServerError => 500,
+            Status::NotImplemented => 501,
+            Status::ServiceUnavailable => 503,
+            Status::HttpVersionNotSupported => 505,
+            Status::Other(code, _) => *code,
+        }
+    }
+
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Status::Ok => "OK",
+            Status::Created => "Created",
+            Status::Accepted => "Accepted",
+            Status::NoContent => "No Content",
+            Status::PartialContent => "Partial Content",
+            Status::MovedPermanently => "Moved Permanently",
+            Status::Found => "Found",
+            Status::SeeOther => "See Other",
+            Status::NotModified => "Not Modified",
+            Status::TemporaryRedirect => "Temporary Redirect",
+            Status::PermanentRedirect => "Permanent Redirect",
+            Status::BadRequest => "Bad Request",
+            Status::Unauthorized => "Unauthorized",
+            Status::Forbidden => "Forbidden",
+            Status::NotFound => "Not Found",
+            Status::MethodNotAllowed => "Method Not Allowed",
+            Status::RequestTimeout => "Request Timeout",
+            Status::PayloadTooLarge => "Payload Too Large",
+            Status::TooManyRequests => "Too Many Requests",
+            Status::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
+            Status::InternalServerError => "Internal Server Error",
+            Status::NotImplemented => "Not Implemented",
+            Status::ServiceUnavailable => "Service Unavailable",
+            Status::HttpVersionNotSupported => "HTTP Version Not Supported",
+            Status::Other(_, reason) => reason,
+        }
+    }
+
+    // 1xx, 204 and 304 responses never carry a body or a length.
+    fn allows_body(&self) -> bool {
+        let code = self.code();
+        !(code < 200 || code == 204 || code == 304)
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.code(), self.reason())
+    }
+}
+
+impl Response {
+    pub fn new(status: Status, body: impl Into<Vec<u8>>) -> Response {
+        Response {
+            status,
+            headers: Headers::new(),
+            body: body.into(),
+        }
+    }
+
+    // Reads the whole file into the body, with a Content-Type to match its
+    // extension, falling back to a 500 response if it can't be read.
+    pub fn file(status: Status, filename: &str) -> Response {
+        match fs::read(filename) {
+            Ok(contents) => Response::new(status, contents)
+                .with_header("Content-Type", mime::from_path(Path::new(filename))),
+            Err(e) => {
+                log!(Level::Error, "Failed to read {}: {}", filename, e);
+                Response::new(Status::InternalServerError, "Internal Server Error\n")
+            }
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Response {
+        self.headers.append(name, value);
+        self
+    }
+
+    pub fn status_code(&self) -> u16 {
+        self.status.code()
+    }
+
+    pub fn write_to<W: Write>(&self, stream: &mut W) -> io::Result<()> {
+        self.write(stream, true)
+    }
+
+    // Used for HEAD requests: same headers, including the Content-Length the
+    // body would have had, but no body.
+    pub fn write_head_to<W: Write>(&self, stream: &mut W) -> io::Result<()> {
+        self.write(stream, false)
+    }
+
+    // Headers the handler didn't set are filled in: Date and Server always,
+    // Content-Type for bodies (a guess between text and bytes), and
+    // Connection: close, since whoever writes a response without choosing
+    // can't be relying on the connection staying open. The server always
+    // sets Connection itself.
+    fn write<W: Write>(&self, stream: &mut W, include_body: bool) -> io::Result<()> {
+        let mut head = Vec::with_capacity(256);
+        write!(head, "HTTP/1.1 {}\r\n", self.status)?;
+        for (name, value) in self.headers.iter() {
+            write!(head, "{}: {}\r\n", name, value)?;
+        }
+        if !self.headers.contains("Date") {
+            write!(head, "Date: {}\r\n", http_date::format(SystemTime::now()))?;
+        }
+        if !self.headers.contains("Server") {
+            write!(head, "Server: {}\r\n", SERVER)?;
+        }
+        let has_body = self.status.allows_body();
+        if has_body && !self.body.is_empty() && !self.headers.contains("Content-Type") {
+            let content_type = match std::str::from_utf8(&self.body) {
+                Ok(_) => "text/plain; charset=utf-8",
+                Err(_) => "application/octet-stream",
+            };
+            write!(head, "Content-Type: {}\r\n", content_type)?;
+        }
+        if !self.headers.contains("Connection") {
+            head.extend_from_slice(b"Connection: close\r\n");
+        }
+        if has_body {
+            write!(head, "Content-Length: {}\r\n", self.body.len())?;
+        }
+        head.extend_from_slice(b"\r\n");
+
+        // One write for small responses; no point copying a large body.
+        if has_body && include_body && self.body.len() <= 16 * 1024 {
+            head.extend_from_slice(&self.body);
+            stream.write_all(&head)?;
+        } else {
+            stream.write_all(&head)?;
+            if has_body && include_body {
+                stream.write_all(&self.body)?;
+            }
+        }
+        stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_in_standard_headers() {
+        let mut out = Vec::new();
+        Response::new(Status::NotFound, "Not Found\n")
+            .write_to(&mut out)
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(out.contains("\r\nDate: "));
+        assert!(out.contains(&format!("\r\nServer: {}\r\n", SERVER)));
+        assert!(out.contains("\r\nContent-Type: text/plain; charset=utf-8\r\n"));
+        assert!(out.contains("\r\nConnection: close\r\n"));
+        assert!(out.ends_with("\r\nContent-Length: 10\r\n\r\nNot Found\n"));
+
+        let mut out = Vec::new();
+        Response::new(Status::Ok, vec![0xff, 0x00])
+            .with_header("Connection", "keep-alive")
+            .write_head_to(&mut out)
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("\r\nContent-Type: application/octet-stream\r\n"));
+        assert_eq!(1, out.matches("Connection:").count());
+        assert!(out.ends_with("Content-Length: 2\r\n\r\n"));
+
+        let mut out = Vec::new();
+        Response::new(Status::NotModified, "")
+            .write_to(&mut out)
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(!out.contains("Content-Length") && !out.contains("Content-Type"));
+        assert_eq!(
+            "418 I'm a teapot",
+            Status::Other(418, "I'm a teapot").to_string()
+        );
+    }
+}
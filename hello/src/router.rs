@@ -0,0 +1,327 @@
+use crate::middleware::Middleware;
+use crate::request::{Method, Request};
+use crate::response::{Response, Status};
+use crate::runtime;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+
+pub type Handler = Box<dyn Fn(&Request) -> Response + Send + Sync + 'static>;
+
+pub type AsyncHandler = Box<
+    dyn Fn(Request) -> Pin<Box<dyn Future<Output = Response> + Send + 'static>>
+        + Send
+        + Sync
+        + 'static,
+>;
+
+// Both kinds of handler work with both kinds of server. The async server
+// awaits async handlers and calls plain ones directly; the threaded server
+// blocks on async ones.
+enum Endpoint {
+    Sync(Handler),
+    Async(AsyncHandler),
+}
+
+// A pattern is split on '/' into segments. `:name` captures exactly one
+// segment and `*` (or `*name`) captures everything that is left, so it may
+// only appear last.
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    Wildcard(Option<String>),
+}
+
+struct Route {
+    method: Method,
+    segments: Vec<Segment>,
+    endpoint: Endpoint,
+}
+
+pub struct Router {
+    routes: Vec<Route>,
+    not_found: Handler,
+    // Outermost first.
+    middleware: Vec<Box<dyn Middleware>>,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router {
+            routes: Vec::new(),
+            not_found: Box::new(|_| Response::new(Status::NotFound, "Not Found\n")),
+            middleware: Vec::new(),
+        }
+    }
+
+    pub fn route<F>(&mut self, method: Method, pattern: &str, handler: F) -> &mut Router
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.routes.push(Route {
+            method,
+            segments: parse_pattern(pattern),
+            endpoint: Endpoint::Sync(Box::new(handler)),
+        });
+        self
+    }
+
+    // For handlers that wait on timers or sockets, so that under the async
+    // server they don't tie up a worker while they wait. They get the
+    // request by value, since the future may outlive the call.
+    pub fn route_async<F, Fut>(&mut self, method: Method, pattern: &str, handler: F) -> &mut Router
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response> + Send + 'static,
+    {
+        self.routes.push(Route {
+            method,
+            segments: parse_pattern(pattern),
+            endpoint: Endpoint::Async(Box::new(move |request| Box::pin(handler(request)))),
+        });
+        self
+    }
+
+    pub fn get<F>(&mut self, pattern: &str, handler: F) -> &mut Router
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    pub fn get_async<F, Fut>(&mut self, pattern: &str, handler: F) -> &mut Router
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response> + Send + 'static,
+    {
+        self.route_async(Method::Get, pattern, handler)
+    }
+
+    pub fn post<F>(&mut self, pattern: &str, handler: F) -> &mut Router
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    pub fn put<F>(&mut self, pattern: &str, handler: F) -> &mut Router
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Put, pattern, handler)
+    }
+
+    pub fn delete<F>(&mut self, pattern: &str, handler: F) -> &mut Router
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Delete, pattern, handler)
+    }
+
+    pub fn not_found<F>(&mut self, handler: F) -> &mut Router
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.not_found = Box::new(handler);
+        self
+    }
+
+    // Adds middleware around every route, including not_found. Each one
+    // added goes inside the ones added before it; see Middleware.
+    pub fn wrap<M: Middleware>(&mut self, middleware: M) -> &mut Router {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    // Routes are tried in the order they were registered and the first
+    // match wins. Captured parameters end up in `request.params`. A HEAD
+    // request without a HEAD route of its own is answered by the GET route.
+    pub fn handle(&self, mut request: Request) -> Response {
+        let (entered, answered) = self.before(&mut request);
+        let endpoint = match answered {
+            Some(_) => None,
+            None => self.lookup(&mut request),
+        };
+        let seen = (entered > 0).then(|| without_body(&request));
+        let mut response = match (answered, endpoint) {
+            (Some(response), _) => response,
+            (None, Some(Endpoint::Sync(handler))) => handler(&request),
+            (None, Some(Endpoint::Async(handler))) => runtime::block_on(handler(request)),
+            (None, None) => (self.not_found)(&request),
+        };
+        if let Some(seen) = seen {
+            self.after(entered, &seen, &mut response);
+        }
+        response
+    }
+
+    pub async fn handle_async(&self, mut request: Request) -> Response {
+        let (entered, answered) = self.before(&mut request);
+        let endpoint = match answered {
+            Some(_) => None,
+            None => self.lookup(&mut request),
+        };
+        let seen = (entered > 0).then(|| without_body(&request));
+        let mut response = match (answered, endpoint) {
+            (Some(response), _) => response,
+            (None, Some(Endpoint::Sync(handler))) => handler(&request),
+            (None, Some(Endpoint::Async(handler))) => handler(request).await,
+            (None, None) => (self.not_found)(&request),
+        };
+        if let Some(seen) = seen {
+            self.after(entered, &seen, &mut response);
+        }
+        response
+    }
+
+    // Runs the middleware's before hooks until one answers. Returns how
+    // many middleware the request got past, which are the ones that see
+    // the response, and the answer if there was one.
+    fn before(&self, request: &mut Request) -> (usize, Option<Response>) {
+        for (i, middleware) in self.middleware.iter().enumerate() {
+            if let Some(response) = middleware.before(request) {
+                return (i, Some(response));
+            }
+        }
+        (self.middleware.len(), None)
+    }
+
+    fn after(&self, entered: usize, request: &Request, response: &mut Response) {
+        for middleware in self.middleware[..entered].iter().rev() {
+            middleware.after(request, response);
+        }
+    }
+
+    fn lookup(&self, request: &mut Request) -> Option<&Endpoint> {
+        let (route, params) = self.find(&request.method, &request.path).or_else(|| {
+            if request.method == Method::Head {
+                self.find(&Method::Get, &request.path)
+            } else {
+                None
+            }
+        })?;
+        request.params = params;
+        Some(&route.endpoint)
+    }
+
+    fn find(&self, method: &Method, path: &str) -> Option<(&Route, HashMap<String, String>)> {
+        self.routes
+            .iter()
+            .filter(|route| route.method == *method)
+            .find_map(|route| match_path(&route.segments, path).map(|params| (route, params)))
+    }
+}
+
+impl Default for Router {
+    fn default() -> Router {
+        Router::new()
+    }
+}
+
+fn without_body(request: &Request) -> Request {
+    Request {
+        method: request.method.clone(),
+        path: request.path.clone(),
+        query: request.query.clone(),
+        version: request.version,
+        headers: request.headers.clone(),
+        body: Vec::new(),
+        params: request.params.clone(),
+    }
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    let segments: Vec<Segment> = pattern
+        .trim_start_matches('/')
+        .split('/')
+        .map(|segment| {
+            if let Some(name) = segment.strip_prefix(':') {
+                Segment::Param(name.to_string())
+            } else if let Some(name) = segment.strip_prefix('*') {
+                Segment::Wildcard(if name.is_empty() {
+                    None
+                } else {
+                    Some(name.to_string())
+                })
+            } else {
+                Segment::Literal(segment.to_string())
+            }
+        })
+        .collect();
+
+    if let Some(position) = segments
+        .iter()
+        .position(|segment| matches!(segment, Segment::Wildcard(_)))
+    {
+        assert!(
+            position == segments.len() - 1,
+            "wildcard must be the last segment in {}",
+            pattern
+        );
+    }
+    segments
+}
+
+fn match_path(segments: &[Segment], path: &str) -> Option<HashMap<String, String>> {
+    let mut params = HashMap::new();
+    let mut parts = path.trim_start_matches('/').split('/');
+
+    for segment in segments {
+        match segment {
+            Segment::Wildcard(name) => {
+                let rest: Vec<&str> = parts.by_ref().collect();
+                if let Some(name) = name {
+                    params.insert(name.clone(), rest.join("/"));
+                }
+                return Some(params);
+            }
+            Segment::Literal(literal) => {
+                if parts.next()? != literal {
+                    return None;
+                }
+            }
+            Segment::Param(name) => {
+                let part = parts.next()?;
+                if part.is_empty() {
+                    return None;
+                }
+                params.insert(name.clone(), part.to_string());
+            }
+        }
+    }
+
+    match parts.next() {
+        None => Some(params),
+        Some(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, path: &str) -> Option<HashMap<String, String>> {
+        match_path(&parse_pattern(pattern), path)
+    }
+
+    #[test]
+    fn matches_literals_and_params() {
+        assert!(matches("/", "/").is_some());
+        assert!(matches("/", "/sleep").is_none());
+        assert!(matches("/sleep", "/sleep").is_some());
+
+        let params = matches("/users/:id", "/users/42").unwrap();
+        assert_eq!("42", params["id"]);
+        assert!(matches("/users/:id", "/users/").is_none());
+        assert!(matches("/users/:id", "/users/42/posts").is_none());
+    }
+
+    #[test]
+    fn wildcard_captures_the_rest() {
+        let params = matches("/static/*path", "/static/css/site.css").unwrap();
+        assert_eq!("css/site.css", params["path"]);
+        assert!(matches("/static/*", "/static").is_some());
+        assert!(matches("/static/*", "/other").is_none());
+    }
+}
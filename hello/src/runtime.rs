@@ -0,0 +1,91 @@
+// The pieces behind the server's async mode: a reactor thread that turns
+// socket readiness and timers into wakeups, non-blocking TCP types and
+// timers built on it, and block_on. Futures themselves run on a ThreadPool;
+// see ThreadPool::spawn_future. The reactor uses epoll, so everything but
+// block_on is Linux-only.
+#[cfg(target_os = "linux")]
+mod net;
+#[cfg(target_os = "linux")]
+mod reactor;
+#[cfg(target_os = "linux")]
+mod time;
+
+#[cfg(target_os = "linux")]
+pub use net::{TcpListener, TcpStream};
+#[cfg(target_os = "linux")]
+pub use time::{sleep, timeout, Sleep};
+
+use std::future::Future;
+use std::pin::pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+// Runs a future to completion on the current thread, parking it whenever
+// the future is waiting. This is how sync code, such as a plain handler,
+// can call into async code.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+struct Unpark(Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::io::prelude::*;
+    use std::net;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn sleeps_and_sockets_wake_their_tasks() {
+        let start = Instant::now();
+        block_on(sleep(Duration::from_millis(30)));
+        assert!(start.elapsed() >= Duration::from_millis(30));
+        let never = block_on(timeout(
+            Duration::from_millis(10),
+            sleep(Duration::from_secs(60)),
+        ));
+        assert_eq!(None, never);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = net::TcpStream::connect(addr).unwrap();
+            thread::sleep(Duration::from_millis(20));
+            stream.write_all(b"ping").unwrap();
+            let mut reply = String::new();
+            stream.read_to_string(&mut reply).unwrap();
+            reply
+        });
+
+        block_on(async {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut buf = Vec::new();
+            while buf.len() < 4 {
+                assert!(stream.read_buf(&mut buf).await.unwrap() > 0);
+            }
+            assert_eq!(b"ping", &buf[..]);
+            stream.write_all(b"pong").await.unwrap();
+        });
+        assert_eq!("pong", client.join().unwrap());
+    }
+}
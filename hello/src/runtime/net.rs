@@ -0,0 +1,125 @@
+use super::reactor::{Reactor, Source};
+use std::future;
+use std::io;
+use std::io::prelude::*;
+use std::net::{self, Shutdown, SocketAddr};
+use std::os::fd::AsRawFd;
+use std::sync::Arc;
+
+// How much read_buf takes in one go before letting other tasks run.
+const READ_BUDGET: usize = 64 * 1024;
+
+// Non-blocking counterparts of the std types, for use in futures. They
+// wrap the std types and register them with the reactor.
+pub struct TcpListener {
+    inner: net::TcpListener,
+    source: Arc<Source>,
+}
+
+pub struct TcpStream {
+    inner: net::TcpStream,
+    source: Arc<Source>,
+}
+
+impl TcpListener {
+    pub fn bind(addr: &str) -> io::Result<TcpListener> {
+        TcpListener::from_std(net::TcpListener::bind(addr)?)
+    }
+
+    pub fn from_std(listener: net::TcpListener) -> io::Result<TcpListener> {
+        listener.set_nonblocking(true)?;
+        let source = Reactor::get().register(listener.as_raw_fd())?;
+        Ok(TcpListener {
+            inner: listener,
+            source,
+        })
+    }
+
+    pub async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        let (stream, addr) =
+            future::poll_fn(|cx| self.source.poll_io(cx, false, || self.inner.accept())).await?;
+        Ok((TcpStream::from_std(stream)?, addr))
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+}
+
+impl TcpStream {
+    pub fn from_std(stream: net::TcpStream) -> io::Result<TcpStream> {
+        stream.set_nonblocking(true)?;
+        let source = Reactor::get().register(stream.as_raw_fd())?;
+        Ok(TcpStream {
+            inner: stream,
+            source,
+        })
+    }
+
+    // The std stream underneath, e.g. for try_clone or setting options.
+    // Reading or writing through it directly will return WouldBlock.
+    pub fn get_ref(&self) -> &net::TcpStream {
+        &self.inner
+    }
+
+    pub async fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        future::poll_fn(|cx| self.source.poll_io(cx, false, || (&self.inner).read(buf))).await
+    }
+
+    // Waits for data, then appends whatever has arrived to `buf`, up to a
+    // limit. Returns 0 once the peer has closed its side.
+    pub async fn read_buf(&self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        let mut chunk = [0; 8 * 1024];
+        let mut total = self.read(&mut chunk).await?;
+        buf.extend_from_slice(&chunk[..total]);
+        while total > 0 && total < READ_BUDGET {
+            match (&self.inner).read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => {
+                    buf.extend_from_slice(&chunk[..n]);
+                    total += n;
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(total)
+    }
+
+    pub async fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        future::poll_fn(|cx| self.source.poll_io(cx, true, || (&self.inner).write(buf))).await
+    }
+
+    pub async fn write_all(&self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.write(buf).await {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => buf = &buf[n..],
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        self.inner.shutdown(how)
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.peer_addr()
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        Reactor::get().deregister(&self.source);
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        Reactor::get().deregister(&self.source);
+    }
+}
@@ -0,0 +1,299 @@
+use crate::log;
+use crate::log::Level;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::Instant;
+
+// epoll data for the eventfd that interrupts epoll_wait.
+const NOTIFY: u64 = 0;
+
+// One reactor thread per process, started on first use. It waits on an
+// epoll instance for socket readiness and on the nearest timer deadline,
+// and wakes whichever tasks are interested. It never runs tasks itself.
+pub(crate) struct Reactor {
+    epoll: OwnedFd,
+    notify: OwnedFd,
+    next_token: AtomicU64,
+    sources: Mutex<HashMap<u64, Arc<Source>>>,
+    // Keyed by (deadline, id) so the first entry is always the next one due.
+    timers: Mutex<BTreeMap<(Instant, u64), Waker>>,
+}
+
+// A registered file descriptor. Registration is edge-triggered, so the
+// reactor only records that the socket became ready; whoever does the I/O
+// keeps going until it sees WouldBlock and only then waits again.
+pub(crate) struct Source {
+    fd: RawFd,
+    token: u64,
+    state: Mutex<Readiness>,
+}
+
+#[derive(Default)]
+struct Readiness {
+    readable: bool,
+    writable: bool,
+    reader: Option<Waker>,
+    writer: Option<Waker>,
+}
+
+impl Reactor {
+    pub(crate) fn get() -> &'static Reactor {
+        static REACTOR: OnceLock<&'static Reactor> = OnceLock::new();
+        REACTOR.get_or_init(|| {
+            let reactor: &'static Reactor =
+                Box::leak(Box::new(Reactor::new().expect("failed to create reactor")));
+            thread::Builder::new()
+                .name("reactor".to_string())
+                .spawn(move || reactor.run())
+                .expect("failed to spawn reactor thread");
+            reactor
+        })
+    }
+
+    fn new() -> io::Result<Reactor> {
+        // SAFETY: plain syscalls; the returned descriptors are checked and
+        // then owned by the OwnedFds.
+        let epoll = unsafe { owned(libc::epoll_create1(libc::EPOLL_CLOEXEC))? };
+        let notify = unsafe { owned(libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK))? };
+        let reactor = Reactor {
+            epoll,
+            notify,
+            next_token: AtomicU64::new(NOTIFY + 1),
+            sources: Mutex::new(HashMap::new()),
+            timers: Mutex::new(BTreeMap::new()),
+        };
+        reactor.ctl(
+            libc::EPOLL_CTL_ADD,
+            reactor.notify.as_raw_fd(),
+            libc::EPOLLIN as u32,
+            NOTIFY,
+        )?;
+        Ok(reactor)
+    }
+
+    fn run(&self) {
+        let mut events = vec![libc::epoll_event { events: 0, u64: 0 }; 256];
+        loop {
+            let timeout = self.fire_timers();
+            // SAFETY: `events` is valid for `events.len()` entries.
+            let count = unsafe {
+                libc::epoll_wait(
+                    self.epoll.as_raw_fd(),
+                    events.as_mut_ptr(),
+                    events.len() as libc::c_int,
+                    timeout,
+                )
+            };
+            if count < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() != io::ErrorKind::Interrupted {
+                    log!(Level::Error, "Reactor failed to wait for events: {}", e);
+                }
+                continue;
+            }
+
+            for event in &events[..count as usize] {
+                let (token, flags) = (event.u64, event.events);
+                if token == NOTIFY {
+                    let mut value = 0u64;
+                    // SAFETY: reads eight bytes into `value`. The eventfd is
+                    // non-blocking; we only care that it's drained.
+                    unsafe {
+                        libc::read(
+                            self.notify.as_raw_fd(),
+                            &mut value as *mut u64 as *mut libc::c_void,
+                            8,
+                        );
+                    }
+                    continue;
+                }
+                let source = lock(&self.sources).get(&token).cloned();
+                if let Some(source) = source {
+                    source.ready(flags);
+                }
+            }
+        }
+    }
+
+    // Wakes the timers that are due and returns how long epoll_wait may
+    // sleep before the next one: -1 for no timers, rounded up to whole
+    // milliseconds so we never wake a timer early.
+    fn fire_timers(&self) -> libc::c_int {
+        let now = Instant::now();
+        let mut due = Vec::new();
+        let timeout = {
+            let mut timers = lock(&self.timers);
+            while let Some(entry) = timers.first_entry() {
+                if entry.key().0 > now {
+                    break;
+                }
+                due.push(entry.remove());
+            }
+            match timers.keys().next() {
+                Some((deadline, _)) => {
+                    let millis = (*deadline - now).as_micros().div_ceil(1000);
+                    millis.min(libc::c_int::MAX as u128) as libc::c_int
+                }
+                None => -1,
+            }
+        };
+        for waker in due {
+            waker.wake();
+        }
+        timeout
+    }
+
+    pub(crate) fn register(&self, fd: RawFd) -> io::Result<Arc<Source>> {
+        let token = self.next_token.fetch_add(1, Ordering::Relaxed);
+        let source = Arc::new(Source {
+            fd,
+            token,
+            // Nothing is known yet, so let the first attempt go ahead.
+            state: Mutex::new(Readiness {
+                readable: true,
+                writable: true,
+                ..Readiness::default()
+            }),
+        });
+        lock(&self.sources).insert(token, Arc::clone(&source));
+        let flags = libc::EPOLLIN | libc::EPOLLOUT | libc::EPOLLRDHUP | libc::EPOLLET;
+        if let Err(e) = self.ctl(libc::EPOLL_CTL_ADD, fd, flags as u32, token) {
+            lock(&self.sources).remove(&token);
+            return Err(e);
+        }
+        Ok(source)
+    }
+
+    // Must be called before the descriptor is closed.
+    pub(crate) fn deregister(&self, source: &Source) {
+        let _ = self.ctl(libc::EPOLL_CTL_DEL, source.fd, 0, source.token);
+        lock(&self.sources).remove(&source.token);
+    }
+
+    fn ctl(&self, op: libc::c_int, fd: RawFd, flags: u32, token: u64) -> io::Result<()> {
+        let mut event = libc::epoll_event {
+            events: flags,
+            u64: token,
+        };
+        // SAFETY: `event` lives across the call; the kernel copies it.
+        let result = unsafe { libc::epoll_ctl(self.epoll.as_raw_fd(), op, fd, &mut event) };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub(crate) fn next_timer_id(&self) -> u64 {
+        self.next_token.fetch_add(1, Ordering::Relaxed)
+    }
+
+    // Adds or refreshes a timer. The reactor is only interrupted if the new
+    // timer is due before everything it's already waiting for.
+    pub(crate) fn set_timer(&self, deadline: Instant, id: u64, waker: &Waker) {
+        let mut timers = lock(&self.timers);
+        let earliest = timers
+            .keys()
+            .next()
+            .is_none_or(|&(first, _)| deadline < first);
+        match timers.get_mut(&(deadline, id)) {
+            Some(existing) => existing.clone_from(waker),
+            None => {
+                timers.insert((deadline, id), waker.clone());
+            }
+        }
+        drop(timers);
+        if earliest {
+            self.notify();
+        }
+    }
+
+    pub(crate) fn cancel_timer(&self, deadline: Instant, id: u64) {
+        lock(&self.timers).remove(&(deadline, id));
+    }
+
+    fn notify(&self) {
+        let value = 1u64;
+        // SAFETY: writes eight bytes from `value`. If the counter is about
+        // to overflow the reactor has plenty of wakeups pending already.
+        unsafe {
+            libc::write(
+                self.notify.as_raw_fd(),
+                &value as *const u64 as *const libc::c_void,
+                8,
+            );
+        }
+    }
+}
+
+impl Source {
+    fn ready(&self, flags: u32) {
+        let readable = (libc::EPOLLIN | libc::EPOLLRDHUP | libc::EPOLLHUP | libc::EPOLLERR) as u32;
+        let writable = (libc::EPOLLOUT | libc::EPOLLHUP | libc::EPOLLERR) as u32;
+        let mut wakers = Vec::new();
+        {
+            let mut state = lock(&self.state);
+            if flags & readable != 0 {
+                state.readable = true;
+                wakers.extend(state.reader.take());
+            }
+            if flags & writable != 0 {
+                state.writable = true;
+                wakers.extend(state.writer.take());
+            }
+        }
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    // Runs a non-blocking operation until it gets past WouldBlock. If the
+    // readiness flag is set when we come to wait, an event arrived since
+    // the last attempt (or the flag is stale), so we clear it and try again
+    // rather than risk sleeping through the edge.
+    pub(crate) fn poll_io<T>(
+        &self,
+        cx: &mut Context<'_>,
+        write: bool,
+        mut op: impl FnMut() -> io::Result<T>,
+    ) -> Poll<io::Result<T>> {
+        loop {
+            match op() {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                result => return Poll::Ready(result),
+            }
+            let mut state = lock(&self.state);
+            let state = &mut *state;
+            let (ready, waker) = if write {
+                (&mut state.writable, &mut state.writer)
+            } else {
+                (&mut state.readable, &mut state.reader)
+            };
+            if *ready {
+                *ready = false;
+                continue;
+            }
+            match waker {
+                Some(waker) => waker.clone_from(cx.waker()),
+                None => *waker = Some(cx.waker().clone()),
+            }
+            return Poll::Pending;
+        }
+    }
+}
+
+// SAFETY: `fd` must be a descriptor the caller owns, or negative on error.
+unsafe fn owned(fd: RawFd) -> io::Result<OwnedFd> {
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(OwnedFd::from_raw_fd(fd))
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
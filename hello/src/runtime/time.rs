@@ -0,0 +1,58 @@
+use super::reactor::Reactor;
+use std::future::{self, Future};
+use std::pin::{pin, Pin};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+// Completes once the duration has passed, without holding up a thread.
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep {
+        deadline: Instant::now() + duration,
+        id: None,
+    }
+}
+
+pub struct Sleep {
+    deadline: Instant,
+    // Set once the timer is registered with the reactor.
+    id: Option<u64>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            if let Some(id) = self.id.take() {
+                Reactor::get().cancel_timer(self.deadline, id);
+            }
+            return Poll::Ready(());
+        }
+        let reactor = Reactor::get();
+        let id = *self.id.get_or_insert_with(|| reactor.next_timer_id());
+        reactor.set_timer(self.deadline, id, cx.waker());
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            Reactor::get().cancel_timer(self.deadline, id);
+        }
+    }
+}
+
+// Runs the future for at most `duration`. Returns None if it didn't
+// finish in time, in which case it is dropped.
+pub async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    let mut future = pin!(future);
+    let mut sleep = pin!(sleep(duration));
+    future::poll_fn(|cx| {
+        if let Poll::Ready(output) = future.as_mut().poll(cx) {
+            return Poll::Ready(Some(output));
+        }
+        sleep.as_mut().poll(cx).map(|()| None)
+    })
+    .await
+}
@@ -0,0 +1,1184 @@
+#[cfg(target_os = "linux")]
+mod framing;
+mod timeout;
+
+use crate::access_log::{AccessLog, Entry};
+use crate::compression::{Compression, Encoding};
+use crate::log;
+use crate::log::Level;
+use crate::request::{self, Method, ParseError, Request, Version};
+use crate::response::{Response, Status};
+use crate::router::Router;
+#[cfg(target_os = "linux")]
+use crate::runtime;
+use crate::tls::Tls;
+use crate::ThreadPool;
+#[cfg(target_os = "linux")]
+use framing::Framing;
+use std::collections::HashMap;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use timeout::{Clock, Timed};
+
+// How long the accept loops wait after a failed accept. Errors like
+// running out of file descriptors persist until some connection closes,
+// and retrying at once would only spin.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone)]
+pub struct ConnectionConfig {
+    // How long an open connection may sit without sending a request.
+    pub idle_timeout: Duration,
+    // How long a client may go quiet in the middle of sending a request.
+    pub read_timeout: Duration,
+    // How long a client gets to send a whole request, from its first byte.
+    // This is what stops a client from holding a worker by trickling in
+    // one byte at a time.
+    pub request_timeout: Duration,
+    // How long one write of the response may block on a client that isn't
+    // reading.
+    pub write_timeout: Duration,
+    // Cap on the request line and headers together; larger requests get a
+    // 431.
+    pub max_header_size: usize,
+    // How many requests are served on one connection before we close it.
+    pub max_requests: usize,
+    // How many connections one client IP may have open at once. Beyond
+    // that, new connections get a 429 and are closed.
+    pub max_connections_per_ip: Option<usize>,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> ConnectionConfig {
+        ConnectionConfig {
+            idle_timeout: Duration::from_secs(5),
+            read_timeout: Duration::from_secs(10),
+            request_timeout: Duration::from_secs(30),
+            write_timeout: Duration::from_secs(10),
+            max_header_size: request::MAX_HEAD_SIZE,
+            max_requests: 100,
+            max_connections_per_ip: None,
+        }
+    }
+}
+
+pub struct Server {
+    listener: TcpListener,
+    pool: ThreadPool,
+    router: Arc<Router>,
+    config: Arc<ConnectionConfig>,
+    shutdown: ShutdownHandle,
+    grace_period: Duration,
+    access_log: Option<Arc<AccessLog>>,
+    compression: Option<Arc<Compression>>,
+    tls: Option<Arc<Tls>>,
+}
+
+// Everything serving a connection needs besides the stream itself.
+#[derive(Clone, Copy)]
+struct Service<'a> {
+    router: &'a Router,
+    config: &'a ConnectionConfig,
+    access_log: Option<&'a AccessLog>,
+    compression: Option<&'a Compression>,
+    tls: Option<&'a Tls>,
+}
+
+impl Server {
+    pub fn new(listener: TcpListener, pool: ThreadPool, router: Router) -> Server {
+        Server {
+            listener,
+            pool,
+            router: Arc::new(router),
+            config: Arc::new(ConnectionConfig::default()),
+            shutdown: ShutdownHandle::new(),
+            grace_period: Duration::from_secs(10),
+            access_log: None,
+            compression: None,
+            tls: None,
+        }
+    }
+
+    pub fn config(mut self, config: ConnectionConfig) -> Server {
+        self.config = Arc::new(config);
+        self
+    }
+
+    // How long in-flight requests get to finish once shutdown starts.
+    pub fn grace_period(mut self, grace_period: Duration) -> Server {
+        self.grace_period = grace_period;
+        self
+    }
+
+    // Records every request served, once its response has been written.
+    pub fn access_log(mut self, log: AccessLog) -> Server {
+        self.access_log = Some(Arc::new(log));
+        self
+    }
+
+    // Compresses bodies for clients that accept it; see Compression.
+    pub fn compression(mut self, compression: Compression) -> Server {
+        self.compression = Some(Arc::new(compression));
+        self
+    }
+
+    // Serves HTTPS instead of plain HTTP. Only the threaded server (run)
+    // supports TLS.
+    pub fn tls(mut self, tls: Tls) -> Server {
+        self.tls = Some(Arc::new(tls));
+        self
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    // Accepts connections until shutdown is requested, then waits up to the
+    // grace period for open connections to finish before forcibly closing
+    // whatever is left and stopping the pool.
+    pub fn run(self) -> io::Result<()> {
+        let Server {
+            listener,
+            pool,
+            router,
+            config,
+            shutdown,
+            grace_period,
+            access_log,
+            compression,
+            tls,
+        } = self;
+        shutdown.set_wake_addr(listener.local_addr()?);
+
+        loop {
+            if shutdown.is_shutdown() {
+                break;
+            }
+            let (stream, peer) = match listener.accept() {
+                Ok((stream, addr)) => (stream, Some(addr.ip())),
+                Err(e) => {
+                    log!(Level::Error, "Failed to accept connection: {}", e);
+                    thread::sleep(ACCEPT_BACKOFF);
+                    continue;
+                }
+            };
+            // This may be the connection shutdown() made to wake us up.
+            if shutdown.is_shutdown() {
+                break;
+            }
+            if shutdown.over_limit(&stream, &config) {
+                // Over TLS there's no way to answer before a handshake.
+                if tls.is_none() {
+                    let response = too_many_connections();
+                    let written = response.write_to(&mut &stream);
+                    log_unparsed(access_log.as_deref(), peer, &response, written.is_ok());
+                }
+                continue;
+            }
+
+            let id = match shutdown.register(&stream) {
+                Ok(id) => id,
+                Err(e) => {
+                    log!(Level::Error, "Failed to register connection: {}", e);
+                    continue;
+                }
+            };
+            // Keep a second handle on the socket so we can still answer if
+            // the pool refuses the job (and drops the stream with it).
+            let overflow = stream.try_clone();
+            let tracked = Tracked {
+                shutdown: shutdown.clone(),
+                id,
+            };
+            let router = Arc::clone(&router);
+            let config = Arc::clone(&config);
+            let job_log = access_log.clone();
+            let compression = compression.clone();
+            let tls_config = tls.clone();
+            let job = pool.try_execute(move || {
+                let service = Service {
+                    router: &router,
+                    config: &config,
+                    access_log: job_log.as_deref(),
+                    compression: compression.as_deref(),
+                    tls: tls_config.as_deref(),
+                };
+                if let Err(e) = serve(stream, service, Some(&tracked)) {
+                    log!(Level::Warn, "Connection error: {}", e);
+                }
+            });
+            // Over TLS we can't answer before the handshake, so the client
+            // just sees the connection close.
+            if job.is_err() && tls.is_none() {
+                if let Ok(mut stream) = overflow {
+                    let response = service_unavailable();
+                    let written = response.write_to(&mut stream);
+                    log_unparsed(access_log.as_deref(), peer, &response, written.is_ok());
+                }
+            }
+        }
+        drop(listener);
+        finish(shutdown, pool, grace_period);
+        Ok(())
+    }
+
+    // Like run, but connections are served by futures on the pool instead of
+    // one job each. A connection only occupies a worker while there is
+    // something to do for it, so idle keep-alive connections and slow
+    // clients cost no threads, and neither do async handlers that are
+    // waiting. Plain handlers still run on, and block, a worker. The async
+    // runtime is built on epoll, so elsewhere this fails with Unsupported.
+    #[cfg(target_os = "linux")]
+    pub fn run_async(self) -> io::Result<()> {
+        let Server {
+            listener,
+            pool,
+            router,
+            config,
+            shutdown,
+            grace_period,
+            access_log,
+            compression,
+            tls,
+        } = self;
+        if tls.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "TLS is only supported by the threaded server",
+            ));
+        }
+        shutdown.set_wake_addr(listener.local_addr()?);
+        let listener = runtime::TcpListener::from_std(listener)?;
+
+        runtime::block_on(async {
+            loop {
+                if shutdown.is_shutdown() {
+                    break;
+                }
+                let (stream, peer) = match listener.accept().await {
+                    Ok((stream, addr)) => (stream, Some(addr.ip())),
+                    Err(e) => {
+                        log!(Level::Error, "Failed to accept connection: {}", e);
+                        runtime::sleep(ACCEPT_BACKOFF).await;
+                        continue;
+                    }
+                };
+                if shutdown.is_shutdown() {
+                    break;
+                }
+                if shutdown.over_limit(stream.get_ref(), &config) {
+                    // Small enough to fit in the socket buffer of a fresh
+                    // connection, so the non-blocking write won't come up
+                    // short.
+                    let response = too_many_connections();
+                    let written = response.write_to(&mut stream.get_ref());
+                    log_unparsed(access_log.as_deref(), peer, &response, written.is_ok());
+                    continue;
+                }
+
+                let id = match shutdown.register(stream.get_ref()) {
+                    Ok(id) => id,
+                    Err(e) => {
+                        log!(Level::Error, "Failed to register connection: {}", e);
+                        continue;
+                    }
+                };
+                let tracked = Tracked {
+                    shutdown: shutdown.clone(),
+                    id,
+                };
+                // As in run, a second handle to answer with if the pool
+                // refuses the connection.
+                let overflow = stream.get_ref().try_clone();
+                let router = Arc::clone(&router);
+                let config = Arc::clone(&config);
+                let job_log = access_log.clone();
+                let compression = compression.clone();
+                let task = pool.try_spawn_future(async move {
+                    let service = Service {
+                        router: &router,
+                        config: &config,
+                        access_log: job_log.as_deref(),
+                        compression: compression.as_deref(),
+                        tls: None,
+                    };
+                    if let Err(e) = serve_async(stream, service, &tracked).await {
+                        log!(Level::Warn, "Connection error: {}", e);
+                    }
+                });
+                if task.is_err() {
+                    if let Ok(mut stream) = overflow {
+                        let response = service_unavailable();
+                        let written = response.write_to(&mut stream);
+                        log_unparsed(access_log.as_deref(), peer, &response, written.is_ok());
+                    }
+                }
+            }
+        });
+        drop(listener);
+        finish(shutdown, pool, grace_period);
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn run_async(self) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the async server is only supported on Linux",
+        ))
+    }
+}
+
+fn finish(shutdown: ShutdownHandle, pool: ThreadPool, grace_period: Duration) {
+    if !shutdown.wait_for_connections(grace_period) {
+        log!(
+            Level::Warn,
+            "Grace period expired; closing remaining connections."
+        );
+        shutdown.close_connections(false);
+    }
+    drop(pool);
+}
+
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    state: Arc<ShutdownState>,
+}
+
+struct ShutdownState {
+    requested: AtomicBool,
+    wake_addr: Mutex<Option<SocketAddr>>,
+    connections: Mutex<Connections>,
+    drained: Condvar,
+}
+
+#[derive(Default)]
+struct Connections {
+    next_id: u64,
+    open: HashMap<u64, OpenConnection>,
+    // Open connections per client address, for max_connections_per_ip.
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+struct OpenConnection {
+    stream: TcpStream,
+    ip: Option<IpAddr>,
+    // True while the connection is waiting for its next keep-alive request.
+    idle: bool,
+}
+
+impl ShutdownHandle {
+    fn new() -> ShutdownHandle {
+        ShutdownHandle {
+            state: Arc::new(ShutdownState {
+                requested: AtomicBool::new(false),
+                wake_addr: Mutex::new(None),
+                connections: Mutex::new(Connections::default()),
+                drained: Condvar::new(),
+            }),
+        }
+    }
+
+    // Stops the server from accepting new connections. Requests that are
+    // being handled run to completion; idle keep-alive connections are
+    // closed right away.
+    pub fn shutdown(&self) {
+        if self.state.requested.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        self.close_connections(true);
+
+        // The accept loop is blocked in accept(); a throwaway connection is
+        // the portable way to wake it up.
+        let addr = *lock(&self.state.wake_addr);
+        if let Some(mut addr) = addr {
+            if addr.ip().is_unspecified() {
+                match addr {
+                    SocketAddr::V4(_) => addr.set_ip([127, 0, 0, 1].into()),
+                    SocketAddr::V6(_) => addr.set_ip(std::net::Ipv6Addr::LOCALHOST.into()),
+                }
+            }
+            let _ = TcpStream::connect_timeout(&addr, Duration::from_secs(1));
+        }
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.state.requested.load(Ordering::SeqCst)
+    }
+
+    fn set_wake_addr(&self, addr: SocketAddr) {
+        *lock(&self.state.wake_addr) = Some(addr);
+    }
+
+    fn register(&self, stream: &TcpStream) -> io::Result<u64> {
+        let ip = stream.peer_addr().ok().map(|addr| addr.ip());
+        let stream = stream.try_clone()?;
+        let mut connections = lock(&self.state.connections);
+        let id = connections.next_id;
+        connections.next_id += 1;
+        connections.open.insert(
+            id,
+            OpenConnection {
+                stream,
+                ip,
+                idle: false,
+            },
+        );
+        if let Some(ip) = ip {
+            *connections.per_ip.entry(ip).or_insert(0) += 1;
+        }
+        Ok(id)
+    }
+
+    fn unregister(&self, id: u64) {
+        let mut connections = lock(&self.state.connections);
+        let ip = connections
+            .open
+            .remove(&id)
+            .and_then(|connection| connection.ip);
+        if let Some(ip) = ip {
+            if let Some(count) = connections.per_ip.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    connections.per_ip.remove(&ip);
+                }
+            }
+        }
+        if connections.open.is_empty() {
+            self.state.drained.notify_all();
+        }
+    }
+
+    // Whether a new connection would take its client past
+    // max_connections_per_ip.
+    fn over_limit(&self, stream: &TcpStream, config: &ConnectionConfig) -> bool {
+        let (Some(limit), Ok(peer)) = (config.max_connections_per_ip, stream.peer_addr()) else {
+            return false;
+        };
+        let open = lock(&self.state.connections)
+            .per_ip
+            .get(&peer.ip())
+            .copied()
+            .unwrap_or(0);
+        if open < limit {
+            return false;
+        }
+        log!(
+            Level::Debug,
+            "Refusing connection from {}: {} already open",
+            peer.ip(),
+            open
+        );
+        true
+    }
+
+    // Returns false if the connection was idle and shutdown has already been
+    // requested, in which case the caller should stop reading.
+    fn set_idle(&self, id: u64, idle: bool) -> bool {
+        let mut connections = lock(&self.state.connections);
+        if let Some(connection) = connections.open.get_mut(&id) {
+            connection.idle = idle;
+        }
+        !(idle && self.is_shutdown())
+    }
+
+    fn close_connections(&self, idle_only: bool) {
+        let connections = lock(&self.state.connections);
+        for connection in connections.open.values() {
+            if connection.idle || !idle_only {
+                let _ = connection.stream.shutdown(Shutdown::Both);
+            }
+        }
+    }
+
+    fn wait_for_connections(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut connections = lock(&self.state.connections);
+        while !connections.open.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            connections = self
+                .state
+                .drained
+                .wait_timeout(connections, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        true
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Ties a connection to the server's shutdown state for as long as it is
+// being served, and unregisters it even if the handler panics.
+struct Tracked {
+    shutdown: ShutdownHandle,
+    id: u64,
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.shutdown.unregister(self.id);
+    }
+}
+
+// Serves requests from one connection until the client closes it, asks us
+// to close it, goes idle, or uses up its request budget. Pipelined requests
+// are answered in order: whatever the client sent ahead stays buffered in
+// the BufReader and is parsed on the next iteration.
+pub fn handle_connection(
+    stream: TcpStream,
+    router: &Router,
+    config: &ConnectionConfig,
+) -> io::Result<()> {
+    let service = Service {
+        router,
+        config,
+        access_log: None,
+        compression: None,
+        tls: None,
+    };
+    serve(stream, service, None)
+}
+
+fn serve(stream: TcpStream, service: Service, tracked: Option<&Tracked>) -> io::Result<()> {
+    stream.set_write_timeout(Some(service.config.write_timeout))?;
+    let peer = stream.peer_addr().ok().map(|addr| addr.ip());
+    let clock = Rc::new(Clock::new(service.config));
+    let stream = Timed::new(stream, Rc::clone(&clock));
+    match service.tls {
+        Some(tls) => {
+            let mut stream = tls.accept(stream)?;
+            let result = serve_stream(&mut stream, peer, &clock, service, tracked);
+            stream.conn.send_close_notify();
+            let _ = stream.flush();
+            result
+        }
+        None => serve_stream(stream, peer, &clock, service, tracked),
+    }
+}
+
+// The request loop, over plain TCP or TLS alike. The stream's reads are
+// timed by `clock`.
+fn serve_stream<S: Read + Write>(
+    stream: S,
+    peer: Option<IpAddr>,
+    clock: &Clock,
+    service: Service,
+    tracked: Option<&Tracked>,
+) -> io::Result<()> {
+    let Service { router, config, .. } = service;
+    let mut reader = BufReader::new(stream);
+
+    for served in 1.. {
+        // Between keep-alive requests the connection counts as idle, unless
+        // the client already pipelined the next request.
+        if let Some(tracked) = tracked {
+            if served > 1
+                && reader.buffer().is_empty()
+                && !tracked.shutdown.set_idle(tracked.id, true)
+            {
+                return Ok(());
+            }
+        }
+        let request = Request::read_with_limit(&mut reader, config.max_header_size);
+        if let Some(tracked) = tracked {
+            tracked.shutdown.set_idle(tracked.id, false);
+        }
+
+        let request = match request {
+            Ok(request) => {
+                clock.finish();
+                request
+            }
+            Err(ParseError::ConnectionClosed) => return Ok(()),
+            // Only worth an answer if the client got as far as starting a
+            // request; an idle connection is just closed.
+            Err(ParseError::Io(e)) if is_timeout(&e) => {
+                if clock.started() {
+                    let response = request_timeout();
+                    let written = response.write_to(reader.get_mut());
+                    log_unparsed(service.access_log, peer, &response, written.is_ok());
+                }
+                return Ok(());
+            }
+            Err(ParseError::Io(e)) => return Err(e),
+            Err(e) => {
+                let response = bad_request(&e);
+                let written = response.write_to(reader.get_mut());
+                log_unparsed(service.access_log, peer, &response, written.is_ok());
+                return written;
+            }
+        };
+
+        let head_only = request.method == Method::Head;
+        let keep_alive = wants_keep_alive(&request) && served < config.max_requests;
+        let (entry, encoding) = service.before(&request, peer);
+        let mut response = router.handle(request);
+        service.after(&mut response, encoding);
+        let keep_alive = set_connection(
+            &mut response,
+            keep_alive,
+            tracked.is_some_and(|tracked| tracked.shutdown.is_shutdown()),
+        );
+
+        let written = if head_only {
+            response.write_head_to(reader.get_mut())
+        } else {
+            response.write_to(reader.get_mut())
+        };
+        // Transfers that broke off are logged too, with nothing sent.
+        service.log(entry, &response, head_only || written.is_err());
+        written?;
+        if !keep_alive {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+async fn serve_async(
+    stream: runtime::TcpStream,
+    service: Service<'_>,
+    tracked: &Tracked,
+) -> io::Result<()> {
+    let Service { router, config, .. } = service;
+    let peer = stream.peer_addr().ok().map(|addr| addr.ip());
+    // Bytes received but not parsed yet, possibly pipelined requests.
+    let mut buffer = Vec::new();
+
+    for served in 1.. {
+        if served > 1 && buffer.is_empty() && !tracked.shutdown.set_idle(tracked.id, true) {
+            return Ok(());
+        }
+        let request = read_request(&stream, &mut buffer, config).await;
+        tracked.shutdown.set_idle(tracked.id, false);
+
+        let request = match request {
+            Ok(request) => request,
+            Err(ParseError::ConnectionClosed) => return Ok(()),
+            // Anything left in the buffer is the start of a request.
+            Err(ParseError::Io(e)) if is_timeout(&e) => {
+                if !buffer.is_empty() {
+                    let response = request_timeout();
+                    let written = write_async(&stream, &response, false, config).await;
+                    log_unparsed(service.access_log, peer, &response, written.is_ok());
+                }
+                return Ok(());
+            }
+            Err(ParseError::Io(e)) => return Err(e),
+            Err(e) => {
+                let response = bad_request(&e);
+                let written = write_async(&stream, &response, false, config).await;
+                log_unparsed(service.access_log, peer, &response, written.is_ok());
+                return written;
+            }
+        };
+
+        let head_only = request.method == Method::Head;
+        let keep_alive = wants_keep_alive(&request) && served < config.max_requests;
+        let (entry, encoding) = service.before(&request, peer);
+        let mut response = router.handle_async(request).await;
+        service.after(&mut response, encoding);
+        let keep_alive = set_connection(&mut response, keep_alive, tracked.shutdown.is_shutdown());
+
+        let written = write_async(&stream, &response, head_only, config).await;
+        service.log(entry, &response, head_only || written.is_err());
+        written?;
+        if !keep_alive {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+// Parses the next request out of `buffer`, reading more from the stream
+// until there is a whole one. The parser works on complete requests, so
+// Framing keeps track of when one has arrived and the parser only runs
+// then. Timeouts work as in the threaded server: the idle timeout until the
+// request starts, then the read timeout per read, up to the request timeout
+// overall.
+#[cfg(target_os = "linux")]
+async fn read_request(
+    stream: &runtime::TcpStream,
+    buffer: &mut Vec<u8>,
+    config: &ConnectionConfig,
+) -> Result<Request, ParseError> {
+    let mut closed = false;
+    let mut deadline = None;
+    let mut framing = Framing::new(config.max_header_size);
+    loop {
+        if !buffer.is_empty() {
+            deadline.get_or_insert_with(|| Instant::now() + config.request_timeout);
+        }
+        if (!buffer.is_empty() && framing.ready(buffer)) || closed {
+            let mut unread = &buffer[..];
+            match Request::read_with_limit(&mut unread, config.max_header_size) {
+                Err(ParseError::ConnectionClosed | ParseError::UnexpectedEof) if !closed => {}
+                result => {
+                    let consumed = buffer.len() - unread.len();
+                    buffer.drain(..consumed);
+                    return result;
+                }
+            }
+        }
+        let wait = match deadline {
+            None => config.idle_timeout,
+            Some(deadline) => deadline
+                .saturating_duration_since(Instant::now())
+                .min(config.read_timeout),
+        };
+        closed = match runtime::timeout(wait, stream.read_buf(buffer)).await {
+            Some(read) => read? == 0,
+            None => return Err(ParseError::Io(io::ErrorKind::TimedOut.into())),
+        };
+    }
+}
+
+#[cfg(target_os = "linux")]
+async fn write_async(
+    stream: &runtime::TcpStream,
+    response: &Response,
+    head_only: bool,
+    config: &ConnectionConfig,
+) -> io::Result<()> {
+    let mut bytes = Vec::new();
+    if head_only {
+        response.write_head_to(&mut bytes)?;
+    } else {
+        response.write_to(&mut bytes)?;
+    }
+    runtime::timeout(config.write_timeout, stream.write_all(&bytes))
+        .await
+        .unwrap_or_else(|| Err(io::ErrorKind::TimedOut.into()))
+}
+
+impl Service<'_> {
+    // Notes what the access log and compression need to know about the
+    // request before the router takes it.
+    fn before(&self, request: &Request, peer: Option<IpAddr>) -> (Option<Entry>, Option<Encoding>) {
+        let entry = self.access_log.map(|_| AccessLog::begin(request, peer));
+        let encoding = self
+            .compression
+            .and_then(|compression| compression.negotiate(request));
+        (entry, encoding)
+    }
+
+    fn after(&self, response: &mut Response, encoding: Option<Encoding>) {
+        if let Some(compression) = self.compression {
+            compression.apply(response, encoding);
+        }
+    }
+
+    // Called once the response has been written, or failed to be. Without
+    // a body sent, as for HEAD, the size is logged as "-".
+    fn log(&self, entry: Option<Entry>, response: &Response, no_body: bool) {
+        if let (Some(log), Some(entry)) = (self.access_log, entry) {
+            let sent = if no_body { 0 } else { response.body.len() };
+            log.finish(entry, response, sent);
+        }
+    }
+}
+
+// `written` is whether the response went out; if not, no bytes were sent.
+fn log_unparsed(
+    access_log: Option<&AccessLog>,
+    peer: Option<IpAddr>,
+    response: &Response,
+    written: bool,
+) {
+    if let Some(log) = access_log {
+        let sent = if written { response.body.len() } else { 0 };
+        log.unparsed(peer, response, sent);
+    }
+}
+
+fn bad_request(e: &ParseError) -> Response {
+    let status = match e {
+        ParseError::HeadTooLarge => Status::RequestHeaderFieldsTooLarge,
+        ParseError::BodyTooLarge => Status::PayloadTooLarge,
+        ParseError::UnknownMethod => Status::NotImplemented,
+        ParseError::UnsupportedVersion => Status::HttpVersionNotSupported,
+        _ => Status::BadRequest,
+    };
+    Response::new(status, format!("{}: {}\n", status.reason(), e))
+        .with_header("Connection", "close")
+}
+
+// Sets the Connection header and returns whether the connection stays
+// open. A handler can also ask for the connection to be closed, and once
+// the server is shutting down this is the last response we send.
+fn set_connection(response: &mut Response, keep_alive: bool, shutting_down: bool) -> bool {
+    let keep_alive =
+        keep_alive && !shutting_down && !has_token(response.headers.get("Connection"), "close");
+    response.headers.remove("Connection");
+    response.headers.append(
+        "Connection",
+        if keep_alive { "keep-alive" } else { "close" },
+    );
+    keep_alive
+}
+
+fn request_timeout() -> Response {
+    Response::new(Status::RequestTimeout, "Request Timeout\n").with_header("Connection", "close")
+}
+
+fn too_many_connections() -> Response {
+    Response::new(Status::TooManyRequests, "Too Many Requests\n")
+        .with_header("Retry-After", "1")
+        .with_header("Connection", "close")
+}
+
+fn service_unavailable() -> Response {
+    Response::new(Status::ServiceUnavailable, "Service Unavailable\n")
+        .with_header("Retry-After", "1")
+        .with_header("Connection", "close")
+}
+
+// HTTP/1.1 connections are persistent unless either side says otherwise,
+// HTTP/1.0 ones only if the client explicitly asks.
+fn wants_keep_alive(request: &Request) -> bool {
+    let connection = request.header("Connection");
+    match request.version {
+        Version::Http11 => !has_token(connection, "close"),
+        Version::Http10 => has_token(connection, "keep-alive"),
+    }
+}
+
+fn has_token(value: Option<&str>, token: &str) -> bool {
+    value.is_some_and(|value| {
+        value
+            .split(',')
+            .any(|part| part.trim().eq_ignore_ascii_case(token))
+    })
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access_log::Format;
+    #[cfg(target_os = "linux")]
+    use crate::pool::OverflowPolicy;
+    use crate::testing::TestResponse;
+    #[cfg(target_os = "linux")]
+    use std::sync::mpsc;
+    use std::thread;
+
+    // Whether to run the server with run_async, for tests that cover both.
+    #[cfg(target_os = "linux")]
+    const MODES: &[bool] = &[false, true];
+    #[cfg(not(target_os = "linux"))]
+    const MODES: &[bool] = &[false];
+
+    #[test]
+    fn shutdown_lets_in_flight_requests_finish() {
+        let mut router = Router::new();
+        router.get("/slow", |_| {
+            thread::sleep(Duration::from_millis(300));
+            Response::new(Status::Ok, "done")
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = Server::new(listener, ThreadPool::new(2), router);
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle();
+        let running = thread::spawn(move || server.run());
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"GET /slow HTTP/1.1\r\n\r\n").unwrap();
+        thread::sleep(Duration::from_millis(100));
+        handle.shutdown();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Connection: close"));
+        assert!(response.ends_with("done"));
+
+        running.join().unwrap().unwrap();
+        assert!(TcpStream::connect(addr).is_err());
+    }
+
+    #[test]
+    fn keeps_connections_alive_up_to_max_requests() {
+        let mut router = Router::new();
+        router.get("/:n", |request| {
+            Response::new(Status::Ok, request.params["n"].clone())
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = Server::new(listener, ThreadPool::new(2), router).config(ConnectionConfig {
+            max_requests: 3,
+            ..ConnectionConfig::default()
+        });
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle();
+        let running = thread::spawn(move || server.run());
+
+        let mut client = BufReader::new(TcpStream::connect(addr).unwrap());
+        client
+            .get_mut()
+            .write_all(b"GET /1 HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let first = TestResponse::read_from(&mut client, false).unwrap();
+        assert_eq!(b"1", &first.body[..]);
+        assert_eq!(Some("keep-alive"), first.header("Connection"));
+
+        // Pipelined requests are answered in order, and the last one the
+        // connection may serve says so.
+        client
+            .get_mut()
+            .write_all(b"GET /2 HTTP/1.1\r\n\r\nGET /3 HTTP/1.1\r\n\r\nGET /4 HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let second = TestResponse::read_from(&mut client, false).unwrap();
+        assert_eq!(b"2", &second.body[..]);
+        assert_eq!(Some("keep-alive"), second.header("Connection"));
+        let third = TestResponse::read_from(&mut client, false).unwrap();
+        assert_eq!(b"3", &third.body[..]);
+        assert_eq!(Some("close"), third.header("Connection"));
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+
+        handle.shutdown();
+        running.join().unwrap().unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn async_mode_serves_many_connections_on_one_worker() {
+        let mut router = Router::new();
+        router
+            .get("/", |_| Response::new(Status::Ok, "sync"))
+            .get_async("/slow", |_| async {
+                runtime::sleep(Duration::from_millis(200)).await;
+                Response::new(Status::Ok, "async")
+            });
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = Server::new(listener, ThreadPool::new(1), router);
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle();
+        let running = thread::spawn(move || server.run_async());
+
+        // An idle connection doesn't keep the only worker busy.
+        let idle = TcpStream::connect(addr).unwrap();
+        let start = Instant::now();
+        let clients: Vec<_> = (0..5)
+            .map(|_| {
+                thread::spawn(move || {
+                    let mut client = TcpStream::connect(addr).unwrap();
+                    client
+                        .write_all(b"GET /slow HTTP/1.1\r\nConnection: close\r\n\r\n")
+                        .unwrap();
+                    let mut response = String::new();
+                    client.read_to_string(&mut response).unwrap();
+                    response
+                })
+            })
+            .collect();
+        for client in clients {
+            assert!(client.join().unwrap().ends_with("async"));
+        }
+        assert!(start.elapsed() < Duration::from_millis(900));
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert_eq!(2, response.matches("HTTP/1.1 200 OK").count());
+
+        drop(idle);
+        handle.shutdown();
+        running.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn requests_split_across_writes_are_put_back_together() {
+        for &async_mode in MODES {
+            let mut router = Router::new();
+            router.post("/echo", |request| {
+                Response::new(Status::Ok, request.body.clone())
+            });
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let server = Server::new(listener, ThreadPool::new(2), router);
+            let addr = server.local_addr().unwrap();
+            let handle = server.shutdown_handle();
+            let running = thread::spawn(move || {
+                if async_mode {
+                    server.run_async()
+                } else {
+                    server.run()
+                }
+            });
+
+            // Each write ends somewhere awkward: inside the head, between a
+            // chunk and its CRLF, and inside a Content-Length body.
+            let mut client = TcpStream::connect(addr).unwrap();
+            let parts: [&[u8]; 6] = [
+                b"POST /echo HTTP/1.1\r\nTransfer-Enc",
+                b"oding: chunked\r\n\r\n5\r\nhello",
+                b"\r\n6\r\n world\r\n0\r\n",
+                b"\r\nPOST /echo HTTP/1.1\r\nConnection: close\r\n",
+                b"Content-Length: 4\r\n\r\nby",
+                b"e!",
+            ];
+            for part in parts {
+                client.write_all(part).unwrap();
+                thread::sleep(Duration::from_millis(50));
+            }
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            assert_eq!(2, response.matches("HTTP/1.1 200 OK").count());
+            assert!(response.contains("\r\n\r\nhello world"));
+            assert!(response.ends_with("\r\n\r\nbye!"));
+
+            handle.shutdown();
+            running.join().unwrap().unwrap();
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn async_mode_turns_connections_away_when_the_pool_is_full() {
+        let (release, blocked) = mpsc::channel::<()>();
+        let blocked = Mutex::new(blocked);
+        let mut router = Router::new();
+        router.get("/block", move |_| {
+            let _ = blocked.lock().unwrap().recv();
+            Response::new(Status::Ok, "done")
+        });
+        let pool = ThreadPool::builder()
+            .num_threads(1)
+            .queue_capacity(1)
+            .overflow_policy(OverflowPolicy::Reject)
+            .build()
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = Server::new(listener, pool, router);
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle();
+        let running = thread::spawn(move || server.run_async());
+
+        // The first connection holds the only worker and the second fills
+        // the queue, so the third is refused.
+        let mut busy = TcpStream::connect(addr).unwrap();
+        busy.write_all(b"GET /block HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        thread::sleep(Duration::from_millis(100));
+        let queued = TcpStream::connect(addr).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        let mut refused = TcpStream::connect(addr).unwrap();
+        let mut response = String::new();
+        refused.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable"));
+
+        drop(release);
+        let mut response = String::new();
+        busy.read_to_string(&mut response).unwrap();
+        assert!(response.ends_with("done"));
+        drop(queued);
+        handle.shutdown();
+        running.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn slow_and_greedy_clients_are_cut_off() {
+        for &async_mode in MODES {
+            let mut router = Router::new();
+            router.get("/", |_| Response::new(Status::Ok, "hello"));
+            let log_path = std::env::temp_dir().join(format!(
+                "hello-cut-off-{}-{}.log",
+                std::process::id(),
+                async_mode
+            ));
+            let _ = std::fs::remove_file(&log_path);
+            let access_log = AccessLog::file(&log_path, Format::Common).unwrap();
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let server = Server::new(listener, ThreadPool::new(2), router)
+                .config(ConnectionConfig {
+                    read_timeout: Duration::from_millis(200),
+                    request_timeout: Duration::from_millis(500),
+                    max_header_size: 256,
+                    max_connections_per_ip: Some(2),
+                    ..ConnectionConfig::default()
+                })
+                .access_log(access_log);
+            let addr = server.local_addr().unwrap();
+            let handle = server.shutdown_handle();
+            let running = thread::spawn(move || {
+                if async_mode {
+                    server.run_async()
+                } else {
+                    server.run()
+                }
+            });
+            let read_all = |mut client: TcpStream| {
+                let mut response = String::new();
+                let _ = client.read_to_string(&mut response);
+                response
+            };
+
+            // A byte every 100ms never trips the read timeout, but the whole
+            // request still has to arrive within the request timeout.
+            let mut slow = TcpStream::connect(addr).unwrap();
+            let start = Instant::now();
+            slow.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+            for _ in 0..8 {
+                thread::sleep(Duration::from_millis(100));
+                if slow.write_all(b"X").is_err() {
+                    break;
+                }
+            }
+            assert!(read_all(slow).starts_with("HTTP/1.1 408 Request Timeout"));
+            assert!(start.elapsed() < Duration::from_secs(2));
+
+            let mut big = TcpStream::connect(addr).unwrap();
+            let cookie = "x".repeat(300);
+            write!(big, "GET / HTTP/1.1\r\nCookie: {}\r\n\r\n", cookie).unwrap();
+            assert!(read_all(big).starts_with("HTTP/1.1 431 "));
+
+            let open: Vec<_> = (0..2).map(|_| TcpStream::connect(addr).unwrap()).collect();
+            thread::sleep(Duration::from_millis(100));
+            let third = TcpStream::connect(addr).unwrap();
+            assert!(read_all(third).starts_with("HTTP/1.1 429 Too Many Requests"));
+            drop(open);
+
+            handle.shutdown();
+            running.join().unwrap().unwrap();
+
+            // None of them got as far as the router, but all are logged.
+            let logged = std::fs::read_to_string(&log_path).unwrap();
+            let statuses: Vec<_> = logged
+                .lines()
+                .map(|line| line.split("\"-\" ").nth(1).unwrap_or(line))
+                .map(|rest| &rest[..3])
+                .collect();
+            assert_eq!(vec!["408", "431", "429"], statuses);
+            std::fs::remove_file(&log_path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use crate::request::{MAX_BODY_SIZE, MAX_CHUNK_LINE};
+
+// Works out from the raw bytes when a whole request has arrived, so that
+// the async server runs the parser once per request rather than over the
+// whole buffer after every read, which would make a large body quadratic.
+// Each call only looks at bytes it hasn't seen yet. It only follows the
+// framing (head, Content-Length, chunks, trailers); whenever the bytes
+// don't make sense to it, it says to parse, and the parser reports the
+// error.
+pub(super) struct Framing {
+    max_head_size: usize,
+    state: State,
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    // Looking for the end of the head; `line` is where the next line starts.
+    Head { line: usize },
+    // Waiting for the buffer to reach `end`.
+    Body { end: usize },
+    // `at` is where the next chunk-size line starts; `total` is the body so
+    // far, size lines included, as the parser counts it.
+    Chunk { at: usize, total: usize },
+    Trailers { at: usize },
+    Ready,
+}
+
+impl Framing {
+    pub(super) fn new(max_head_size: usize) -> Framing {
+        Framing {
+            max_head_size,
+            state: State::Head { line: 0 },
+        }
+    }
+
+    // Whether `buffer`, which only ever grows between calls, may hold a
+    // whole request and is worth parsing.
+    pub(super) fn ready(&mut self, buffer: &[u8]) -> bool {
+        loop {
+            self.state = match self.state {
+                State::Ready => return true,
+                State::Head { line } => match self.head(buffer, line) {
+                    Some(state) => state,
+                    None => return false,
+                },
+                State::Body { end } if buffer.len() >= end => State::Ready,
+                State::Body { .. } => return false,
+                State::Chunk { at, total } => {
+                    let Some((line, next)) = self.line(buffer, at) else {
+                        if buffer.len() - at > MAX_CHUNK_LINE {
+                            self.state = State::Ready;
+                            return true;
+                        }
+                        return false;
+                    };
+                    let total = total + (next - at);
+                    let size = line.split(|&b| b == b';').next().unwrap_or(b"");
+                    let size = std::str::from_utf8(size)
+                        .ok()
+                        .and_then(|size| usize::from_str_radix(size.trim(), 16).ok());
+                    match size {
+                        Some(0) => State::Trailers { at: next },
+                        Some(size) if size <= MAX_BODY_SIZE.saturating_sub(total) => {
+                            let end = next + size + 2;
+                            if buffer.len() < end {
+                                return false;
+                            }
+                            State::Chunk {
+                                at: end,
+                                total: total + size + 2,
+                            }
+                        }
+                        _ => State::Ready,
+                    }
+                }
+                State::Trailers { at } => match self.line(buffer, at) {
+                    Some((b"", _)) => State::Ready,
+                    Some((_, next)) => State::Trailers { at: next },
+                    None => return self.too_long(buffer.len() - at),
+                },
+            };
+        }
+    }
+
+    // Scans header lines from `line`, returning the next state once the
+    // empty line that ends the head is in.
+    fn head(&mut self, buffer: &[u8], mut line: usize) -> Option<State> {
+        loop {
+            let Some((text, next)) = self.line(buffer, line) else {
+                self.state = State::Head { line };
+                return self.too_long(buffer.len()).then_some(State::Ready);
+            };
+            if text.is_empty() && has_request_line(&buffer[..line]) {
+                return Some(body_state(&buffer[..line], next));
+            }
+            line = next;
+        }
+    }
+
+    // The line starting at `at` without its line ending, and where the one
+    // after it starts.
+    fn line<'a>(&self, buffer: &'a [u8], at: usize) -> Option<(&'a [u8], usize)> {
+        let end = at + buffer[at..].iter().position(|&b| b == b'\n')?;
+        let line = &buffer[at..end];
+        Some((line.strip_suffix(b"\r").unwrap_or(line), end + 1))
+    }
+
+    // Past the head limit without the line ending we're waiting for; the
+    // parser will refuse it.
+    fn too_long(&mut self, len: usize) -> bool {
+        if len > self.max_head_size {
+            self.state = State::Ready;
+            return true;
+        }
+        false
+    }
+}
+
+// The parser skips empty lines before the request line.
+fn has_request_line(head: &[u8]) -> bool {
+    head.iter().any(|&b| b != b'\r' && b != b'\n')
+}
+
+fn body_state(head: &[u8], end: usize) -> State {
+    let mut content_length = None;
+    let mut chunked = false;
+    for line in head.split(|&b| b == b'\n') {
+        let Some((name, value)) = std::str::from_utf8(line)
+            .ok()
+            .and_then(|line| line.split_once(':'))
+        else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("Content-Length") {
+            content_length = value.parse::<usize>().ok();
+            if content_length.is_none() {
+                return State::Ready;
+            }
+        } else if name.eq_ignore_ascii_case("Transfer-Encoding") {
+            let last = value.rsplit(',').next().unwrap_or("").trim();
+            if !last.eq_ignore_ascii_case("chunked") {
+                return State::Ready;
+            }
+            chunked = true;
+        }
+    }
+    match (chunked, content_length) {
+        (false, None) => State::Ready,
+        (false, Some(length)) if length <= MAX_BODY_SIZE => State::Body { end: end + length },
+        (true, None) => State::Chunk { at: end, total: 0 },
+        // Too large, or both kinds of framing; the parser refuses these.
+        _ => State::Ready,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Feeds `raw` one byte at a time and returns how long it was when the
+    // framing first said to parse.
+    fn ready_at(raw: &[u8]) -> Option<usize> {
+        let mut framing = Framing::new(1024);
+        (1..=raw.len()).find(|&len| framing.ready(&raw[..len]))
+    }
+
+    #[test]
+    fn waits_for_the_whole_request() {
+        let get = b"\r\nGET / HTTP/1.1\r\nHost: x\r\n\r\n";
+        assert_eq!(Some(get.len()), ready_at(get));
+
+        let post = b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello";
+        assert_eq!(Some(post.len()), ready_at(post));
+
+        let chunked = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                        5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\nX-Trailer: yes\r\n\r\n";
+        assert_eq!(Some(chunked.len()), ready_at(chunked));
+
+        // Nonsense goes to the parser as soon as it shows.
+        let bad = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n";
+        assert_eq!(Some(bad.len()), ready_at(bad));
+        assert_eq!(Some(1025), ready_at(&[b'a'; 2000]));
+    }
+}
@@ -0,0 +1,110 @@
+use super::ConnectionConfig;
+use std::cell::Cell;
+use std::io;
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+// Tracks where a connection is in its current request, to pick the read
+// timeout: the idle timeout while waiting for a request to start, then the
+// (shorter) read timeout per read, and never past the request deadline. A
+// client that trickles in a byte at a time to dodge the read timeout still
+// runs into the deadline.
+pub(super) struct Clock {
+    idle_timeout: Duration,
+    read_timeout: Duration,
+    request_timeout: Duration,
+    // Set by the first byte of a request, cleared once it has been read.
+    deadline: Cell<Option<Instant>>,
+}
+
+// The socket under a connection, with the Clock applied to every read. TLS
+// goes on top of this, so the handshake counts towards the first request.
+pub(super) struct Timed {
+    stream: TcpStream,
+    clock: Rc<Clock>,
+    // The read timeout currently set on the socket.
+    current: Option<Duration>,
+}
+
+impl Clock {
+    pub(super) fn new(config: &ConnectionConfig) -> Clock {
+        Clock {
+            idle_timeout: config.idle_timeout,
+            read_timeout: config.read_timeout,
+            request_timeout: config.request_timeout,
+            deadline: Cell::new(None),
+        }
+    }
+
+    // Whether part of a request has arrived.
+    pub(super) fn started(&self) -> bool {
+        self.deadline.get().is_some()
+    }
+
+    fn start(&self) {
+        if self.deadline.get().is_none() {
+            self.deadline
+                .set(Some(Instant::now() + self.request_timeout));
+        }
+    }
+
+    // The request has been read; back to waiting for the next one.
+    pub(super) fn finish(&self) {
+        self.deadline.set(None);
+    }
+
+    // How long the next read may wait, or TimedOut if the request deadline
+    // has already passed.
+    pub(super) fn next_timeout(&self) -> io::Result<Duration> {
+        match self.deadline.get() {
+            None => Ok(self.idle_timeout),
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "request took too long to arrive",
+                    ));
+                }
+                Ok(left.min(self.read_timeout))
+            }
+        }
+    }
+}
+
+impl Timed {
+    pub(super) fn new(stream: TcpStream, clock: Rc<Clock>) -> Timed {
+        Timed {
+            stream,
+            clock,
+            current: None,
+        }
+    }
+}
+
+impl Read for Timed {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timeout = self.clock.next_timeout()?;
+        if self.current != Some(timeout) {
+            self.stream.set_read_timeout(Some(timeout))?;
+            self.current = Some(timeout);
+        }
+        let read = self.stream.read(buf)?;
+        if read > 0 {
+            self.clock.start();
+        }
+        Ok(read)
+    }
+}
+
+impl Write for Timed {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
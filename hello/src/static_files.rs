@@ -0,0 +1,254 @@
+use crate::http_date;
+use crate::log;
+use crate::log::Level;
+use crate::mime;
+use crate::request::Request;
+use crate::response::{Response, Status};
+use crate::tls;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub struct StaticFiles {
+    root: PathBuf,
+    index: String,
+}
+
+impl StaticFiles {
+    pub fn new(root: impl Into<PathBuf>) -> StaticFiles {
+        StaticFiles {
+            root: root.into(),
+            index: String::from("index.html"),
+        }
+    }
+
+    pub fn index(mut self, index: &str) -> StaticFiles {
+        self.index = index.to_string();
+        self
+    }
+
+    // Serves `relative` (usually the `*path` captured by the router) from
+    // the root directory.
+    pub fn serve(&self, request: &Request, relative: &str) -> Response {
+        let path = match self.resolve(relative) {
+            Some(path) => path,
+            None => return Response::new(Status::Forbidden, "Forbidden\n"),
+        };
+
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(e) => return error_response(e),
+        };
+        if metadata.is_dir() {
+            // Relative links in the index only work if the URL ends in '/'.
+            if !request.path.ends_with('/') {
+                // The path is decoded, so it's encoded again to make a valid
+                // header, and "//host" would send the client to another
+                // site, so leading slashes become one.
+                let mut location = String::from("/");
+                tls::encode_path(request.path.trim_start_matches('/'), &mut location);
+                location.push('/');
+                return Response::new(Status::MovedPermanently, "")
+                    .with_header("Location", &location);
+            }
+            let index = path.join(&self.index);
+            if !self.contains(&index) {
+                return Response::new(Status::Forbidden, "Forbidden\n");
+            }
+            return self.serve_file(request, &index);
+        }
+        self.serve_file(request, &path)
+    }
+
+    fn serve_file(&self, request: &Request, path: &Path) -> Response {
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) if metadata.is_file() => metadata,
+            Ok(_) => return Response::new(Status::NotFound, "Not Found\n"),
+            Err(e) => return error_response(e),
+        };
+        let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+        let etag = etag(metadata.len(), modified);
+        let last_modified = http_date::format(modified);
+
+        // The 304 keeps the Content-Type and length of the file so
+        // Compression can tell whether the 200 would have been compressed.
+        if not_modified(request, &etag, modified) {
+            return Response::new(Status::NotModified, "")
+                .with_header("Content-Type", mime::from_path(path))
+                .with_header("Content-Length", &metadata.len().to_string())
+                .with_header("ETag", &etag)
+                .with_header("Last-Modified", &last_modified);
+        }
+
+        match fs::read(path) {
+            Ok(contents) => Response::new(Status::Ok, contents)
+                .with_header("Content-Type", mime::from_path(path))
+                .with_header("ETag", &etag)
+                .with_header("Last-Modified", &last_modified),
+            Err(e) => error_response(e),
+        }
+    }
+
+    // Joins the request path onto the root, refusing anything that could
+    // climb out of it: `..` components, absolute paths, and symlinks that
+    // point outside the root.
+    fn resolve(&self, relative: &str) -> Option<PathBuf> {
+        let mut path = self.root.clone();
+        for segment in relative.split('/') {
+            if segment.contains('\\') {
+                return None;
+            }
+            match Path::new(segment).components().next() {
+                None | Some(Component::CurDir) => {}
+                Some(Component::Normal(part)) => path.push(part),
+                Some(_) => return None,
+            }
+        }
+
+        self.contains(&path).then_some(path)
+    }
+
+    // Whether `path` is still under the root once symlinks are followed.
+    fn contains(&self, path: &Path) -> bool {
+        let Ok(root) = self.root.canonicalize() else {
+            return false;
+        };
+        match path.canonicalize() {
+            Ok(canonical) => canonical.starts_with(&root),
+            // Missing files are reported as 404 by the caller.
+            Err(_) => true,
+        }
+    }
+}
+
+fn etag(len: u64, modified: SystemTime) -> String {
+    let modified = modified
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos())
+        .unwrap_or(0);
+    format!("\"{:x}-{:x}\"", len, modified)
+}
+
+// If-None-Match takes precedence; If-Modified-Since is only consulted when
+// the client didn't send an entity tag.
+fn not_modified(request: &Request, etag: &str, modified: SystemTime) -> bool {
+    if let Some(candidates) = request.header("If-None-Match") {
+        return candidates
+            .split(',')
+            .map(|tag| tag.trim())
+            .any(|tag| tag == "*" || same_file(tag.strip_prefix("W/").unwrap_or(tag), etag));
+    }
+    if let Some(since) = request
+        .header("If-Modified-Since")
+        .and_then(http_date::parse)
+    {
+        let modified = modified
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        let since = since
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        return modified <= since;
+    }
+    false
+}
+
+// Compression tags the ETag of an encoded body with the encoding, e.g.
+// "abc-gzip"; that still names the same file.
+fn same_file(tag: &str, etag: &str) -> bool {
+    let Some(untagged) = etag.strip_suffix('"') else {
+        return tag == etag;
+    };
+    tag == etag
+        || ["-gzip\"", "-deflate\""]
+            .iter()
+            .any(|suffix| tag.strip_suffix(suffix) == Some(untagged))
+}
+
+fn error_response(e: io::Error) -> Response {
+    match e.kind() {
+        io::ErrorKind::NotFound => Response::new(Status::NotFound, "Not Found\n"),
+        io::ErrorKind::PermissionDenied => Response::new(Status::Forbidden, "Forbidden\n"),
+        _ => {
+            log!(Level::Error, "Failed to serve static file: {}", e);
+            Response::new(Status::InternalServerError, "Internal Server Error\n")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use std::os::unix::fs::symlink;
+
+    fn get(files: &StaticFiles, path: &str, headers: &str) -> Response {
+        let raw = format!("GET /{} HTTP/1.1\r\n{}\r\n", path, headers);
+        let request = Request::read_from(&mut raw.as_bytes()).unwrap();
+        // What the router would capture for "/*path".
+        let relative = request.path.trim_start_matches('/').to_string();
+        files.serve(&request, &relative)
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn serves_only_what_is_under_the_root() {
+        let dir = std::env::temp_dir().join(format!("hello-static-{}", std::process::id()));
+        let root = dir.join("root");
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::create_dir_all(root.join("linked")).unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::write(root.join("docs/index.html"), "<p>docs</p>").unwrap();
+        fs::write(dir.join("secret.txt"), "secret").unwrap();
+        symlink(dir.join("secret.txt"), root.join("linked/index.html")).unwrap();
+        symlink(dir.join("secret.txt"), root.join("secret.txt")).unwrap();
+        let files = StaticFiles::new(&root);
+
+        let response = get(&files, "a.txt", "");
+        assert_eq!(Status::Ok, response.status);
+        assert_eq!(b"a", &response.body[..]);
+        for path in [
+            "../secret.txt",
+            "docs/../../secret.txt",
+            "docs\\..\\a.txt",
+            "secret.txt",
+        ] {
+            assert_eq!(Status::Forbidden, get(&files, path, "").status, "{}", path);
+        }
+
+        let response = get(&files, "docs/", "");
+        assert_eq!(b"<p>docs</p>", &response.body[..]);
+        let response = get(&files, "docs", "");
+        assert_eq!(Status::MovedPermanently, response.status);
+        assert_eq!(Some("/docs/"), response.headers.get("Location"));
+        fs::create_dir_all(root.join("my docs")).unwrap();
+        let response = get(&files, "my%20docs", "");
+        assert_eq!(Some("/my%20docs/"), response.headers.get("Location"));
+        let response = get(&files, "/docs", "");
+        assert_eq!(Some("/docs/"), response.headers.get("Location"));
+        assert_eq!(Status::Forbidden, get(&files, "linked/", "").status);
+
+        let response = get(&files, "a.txt", "");
+        let etag = response.headers.get("ETag").unwrap();
+        let last_modified = response.headers.get("Last-Modified").unwrap();
+        for header in [
+            format!("If-None-Match: \"x\", {}\r\n", etag),
+            format!("If-Modified-Since: {}\r\n", last_modified),
+        ] {
+            let response = get(&files, "a.txt", &header);
+            assert_eq!(Status::NotModified, response.status);
+            assert!(response.body.is_empty());
+        }
+        // If-None-Match wins over a date that would have matched.
+        let header = format!(
+            "If-None-Match: \"x\"\r\nIf-Modified-Since: {}\r\n",
+            last_modified
+        );
+        assert_eq!(Status::Ok, get(&files, "a.txt", &header).status);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
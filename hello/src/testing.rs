@@ -0,0 +1,294 @@
+use crate::headers::Headers;
+use crate::router::Router;
+use crate::server::{Server, ShutdownHandle};
+use crate::ThreadPool;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread::{self, JoinHandle};
+
+// A server running on its own thread on an ephemeral port, for tests. It
+// is shut down when dropped, or with stop() to see how the run ended.
+//
+//     let server = TestServer::start(router);
+//     let response = server.client().get("/").unwrap();
+//     assert_eq!(200, response.status);
+pub struct TestServer {
+    addr: SocketAddr,
+    shutdown: ShutdownHandle,
+    running: Option<JoinHandle<io::Result<()>>>,
+}
+
+// Sends requests to one server, reusing the connection for as long as the
+// server keeps it open.
+pub struct Client {
+    addr: SocketAddr,
+    connection: Option<BufReader<TcpStream>>,
+}
+
+pub struct RequestBuilder<'a> {
+    client: &'a mut Client,
+    method: String,
+    target: String,
+    headers: Headers,
+    body: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TestResponse {
+    pub status: u16,
+    pub reason: String,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl TestServer {
+    // Serves `router` on the threaded server with default settings.
+    pub fn start(router: Router) -> TestServer {
+        TestServer::start_with(|listener| Server::new(listener, ThreadPool::new(4), router))
+    }
+
+    // For servers that need more setup: `build` gets the listener and
+    // returns the server to run on it.
+    pub fn start_with<F>(build: F) -> TestServer
+    where
+        F: FnOnce(TcpListener) -> Server,
+    {
+        TestServer::spawn(build, Server::run)
+    }
+
+    // Like start_with, but runs the async server.
+    pub fn start_async_with<F>(build: F) -> TestServer
+    where
+        F: FnOnce(TcpListener) -> Server,
+    {
+        TestServer::spawn(build, Server::run_async)
+    }
+
+    fn spawn<F>(build: F, run: fn(Server) -> io::Result<()>) -> TestServer
+    where
+        F: FnOnce(TcpListener) -> Server,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("failed to bind a test port");
+        let server = build(listener);
+        let addr = server.local_addr().expect("test server has no address");
+        let shutdown = server.shutdown_handle();
+        let running = thread::spawn(move || run(server));
+        TestServer {
+            addr,
+            shutdown,
+            running: Some(running),
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn client(&self) -> Client {
+        Client::new(self.addr)
+    }
+
+    // Shuts the server down and waits for it, returning what run returned.
+    pub fn stop(mut self) -> io::Result<()> {
+        self.shutdown.shutdown();
+        match self.running.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err(io::Error::other("test server panicked")),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.shutdown.shutdown();
+        if let Some(running) = self.running.take() {
+            let _ = running.join();
+        }
+    }
+}
+
+impl Client {
+    pub fn new(addr: SocketAddr) -> Client {
+        Client {
+            addr,
+            connection: None,
+        }
+    }
+
+    pub fn get(&mut self, target: &str) -> io::Result<TestResponse> {
+        self.request("GET", target).send()
+    }
+
+    pub fn head(&mut self, target: &str) -> io::Result<TestResponse> {
+        self.request("HEAD", target).send()
+    }
+
+    pub fn post(&mut self, target: &str, body: impl Into<Vec<u8>>) -> io::Result<TestResponse> {
+        self.request("POST", target).body(body).send()
+    }
+
+    // `target` is sent as is, so it may include a query string and must
+    // already be percent-encoded.
+    pub fn request(&mut self, method: &str, target: &str) -> RequestBuilder<'_> {
+        RequestBuilder {
+            client: self,
+            method: method.to_string(),
+            target: target.to_string(),
+            headers: Headers::new(),
+            body: Vec::new(),
+        }
+    }
+
+    // Writes raw bytes on the current connection (opening one if needed)
+    // and reads one response, for requests the builder can't express.
+    pub fn send_raw(&mut self, bytes: &[u8], head_only: bool) -> io::Result<TestResponse> {
+        // The server may have closed a kept connection as idle; if that's
+        // why it failed, try once more on a fresh one.
+        if let Some(connection) = self.connection.take() {
+            match self.exchange(connection, bytes, head_only) {
+                Err(e) if is_closed(&e) => {}
+                result => return result,
+            }
+        }
+        let connection = BufReader::new(TcpStream::connect(self.addr)?);
+        self.exchange(connection, bytes, head_only)
+    }
+
+    fn exchange(
+        &mut self,
+        mut connection: BufReader<TcpStream>,
+        bytes: &[u8],
+        head_only: bool,
+    ) -> io::Result<TestResponse> {
+        connection.get_mut().write_all(bytes)?;
+        let response = TestResponse::read_from(&mut connection, head_only)?;
+        // Keep the connection only if the server will.
+        if response.header("Connection") != Some("close") {
+            self.connection = Some(connection);
+        }
+        Ok(response)
+    }
+}
+
+impl RequestBuilder<'_> {
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    // Host and, for a body, Content-Length are filled in unless set.
+    pub fn send(self) -> io::Result<TestResponse> {
+        let mut bytes = Vec::with_capacity(256 + self.body.len());
+        write!(bytes, "{} {} HTTP/1.1\r\n", self.method, self.target)?;
+        if !self.headers.contains("Host") {
+            write!(bytes, "Host: {}\r\n", self.client.addr)?;
+        }
+        for (name, value) in self.headers.iter() {
+            write!(bytes, "{}: {}\r\n", name, value)?;
+        }
+        if !self.body.is_empty() && !self.headers.contains("Content-Length") {
+            write!(bytes, "Content-Length: {}\r\n", self.body.len())?;
+        }
+        bytes.extend_from_slice(b"\r\n");
+        bytes.extend_from_slice(&self.body);
+        let head_only = self.method.eq_ignore_ascii_case("HEAD");
+        self.client.send_raw(&bytes, head_only)
+    }
+}
+
+impl TestResponse {
+    // Reads one response. The body is Content-Length bytes, or everything
+    // up to the end of the connection if there is no length and the server
+    // said it will close it; responses to HEAD, 204 and 304 have none.
+    pub fn read_from<R: BufRead>(reader: &mut R, head_only: bool) -> io::Result<TestResponse> {
+        let status_line = read_line(reader)?;
+        let mut parts = status_line.splitn(3, ' ');
+        let (Some("HTTP/1.1" | "HTTP/1.0"), Some(code), reason) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid(format!("bad status line {:?}", status_line)));
+        };
+        let status = code
+            .parse()
+            .map_err(|_| invalid(format!("bad status code {:?}", code)))?;
+
+        let mut headers = Headers::new();
+        loop {
+            let line = read_line(reader)?;
+            if line.is_empty() {
+                break;
+            }
+            let Some((name, value)) = line.split_once(':') else {
+                return Err(invalid(format!("bad header line {:?}", line)));
+            };
+            headers.append(name.trim(), value.trim());
+        }
+
+        let mut body = Vec::new();
+        if !head_only && status != 204 && status != 304 {
+            match headers.get("Content-Length") {
+                Some(length) => {
+                    let length = length
+                        .parse()
+                        .map_err(|_| invalid(format!("bad Content-Length {:?}", length)))?;
+                    body.resize(length, 0);
+                    reader.read_exact(&mut body)?;
+                }
+                None if headers.get("Connection") == Some("close") => {
+                    reader.read_to_end(&mut body)?;
+                }
+                // Reading to the end of a kept connection would never finish.
+                None => return Err(invalid("response has no Content-Length".to_string())),
+            }
+        }
+        Ok(TestResponse {
+            status,
+            reason: reason.unwrap_or("").to_string(),
+            headers,
+            body,
+        })
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    // The body as text, with anything that isn't UTF-8 replaced.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+// A line without its CRLF. Running out of input before one is an error.
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed before the response was complete",
+        ));
+    }
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn is_closed(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+    )
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,203 @@
+use crate::request::Request;
+use crate::response::{Response, Status};
+use crate::router::Router;
+use rustls::crypto::ring;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+
+pub type TlsStream<S = TcpStream> = StreamOwned<ServerConnection, S>;
+
+// A certificate chain and private key to serve HTTPS with. Give it to
+// Server::tls and every accepted connection does a TLS handshake before
+// its requests are read; everything after that is the same as for plain
+// HTTP.
+#[derive(Clone)]
+pub struct Tls {
+    config: Arc<ServerConfig>,
+}
+
+impl Tls {
+    // Reads a PEM certificate chain (leaf first) and a PEM private key in
+    // PKCS#8, PKCS#1 or SEC1 form.
+    pub fn from_pem_files(cert: &Path, key: &Path) -> io::Result<Tls> {
+        let read = |path: &Path| {
+            fs::read(path)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+        };
+        Tls::from_pem(&read(cert)?, &read(key)?)
+    }
+
+    pub fn from_pem(cert: &[u8], key: &[u8]) -> io::Result<Tls> {
+        let certs = CertificateDer::pem_slice_iter(cert)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| invalid(format!("bad certificate PEM: {}", e)))?;
+        if certs.is_empty() {
+            return Err(invalid("no certificate found in PEM".to_string()));
+        }
+        let key = PrivateKeyDer::from_pem_slice(key)
+            .map_err(|e| invalid(format!("bad private key PEM: {}", e)))?;
+        let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+            .map_err(|e| invalid(e.to_string()))?;
+        Ok(Tls {
+            config: Arc::new(config),
+        })
+    }
+
+    // The handshake itself happens on the first read or write, so it runs
+    // on the worker and is subject to the connection's timeouts.
+    pub(crate) fn accept<S: Read + Write>(&self, stream: S) -> io::Result<TlsStream<S>> {
+        let connection = ServerConnection::new(Arc::clone(&self.config)).map_err(invalid_data)?;
+        Ok(StreamOwned::new(connection, stream))
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn invalid_data(e: rustls::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+// Answers every request with a redirect to the same URL on HTTPS, for a
+// plain HTTP listener next to the real one. `https_port` is left out of
+// the URL if it's 443. GET and HEAD get a 301; anything else a 308, which
+// tells the client to repeat the method and body. The host in the URL is
+// copied from the request's Host header as is: nothing checks it against
+// the names the certificate covers.
+pub fn redirect_router(https_port: u16) -> Router {
+    let mut router = Router::new();
+    router.not_found(move |request| {
+        let Some(location) = https_url(request, https_port) else {
+            return Response::new(Status::BadRequest, "Missing Host header\n");
+        };
+        let status = match request.method.as_str() {
+            "GET" | "HEAD" => Status::MovedPermanently,
+            _ => Status::PermanentRedirect,
+        };
+        Response::new(status, "").with_header("Location", &location)
+    });
+    router
+}
+
+fn https_url(request: &Request, https_port: u16) -> Option<String> {
+    let host = request.header("Host")?.trim();
+    // Drop any port, keeping IPv6 literals like [::1] intact.
+    let host = match host.rfind(':') {
+        Some(i) if !host[i..].contains(']') => &host[..i],
+        _ => host,
+    };
+    if host.is_empty() {
+        return None;
+    }
+    let mut url = format!("https://{}", host);
+    if https_port != 443 {
+        let _ = write!(url, ":{}", https_port);
+    }
+    encode_path(&request.path, &mut url);
+    if let Some(query) = &request.query {
+        url.push('?');
+        url.push_str(query);
+    }
+    Some(url)
+}
+
+// The router hands us the decoded path, so encode it again for the
+// Location header.
+pub(crate) fn encode_path(path: &str, out: &mut String) {
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(byte as char)
+            }
+            _ => {
+                let _ = write!(out, "%{:02X}", byte);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::Server;
+    use crate::ThreadPool;
+    use rustls::{ClientConfig, ClientConnection, RootCertStore};
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn serves_https_with_a_self_signed_certificate() {
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let tls = Tls::from_pem(
+            generated.cert.pem().as_bytes(),
+            generated.key_pair.serialize_pem().as_bytes(),
+        )
+        .unwrap();
+        let mut router = Router::new();
+        router.get("/", |_| Response::new(Status::Ok, "secure"));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = Server::new(listener, ThreadPool::new(2), router).tls(tls);
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle();
+        let running = thread::spawn(move || server.run());
+
+        let mut roots = RootCertStore::empty();
+        roots.add(generated.cert.der().clone()).unwrap();
+        let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connection =
+            ClientConnection::new(Arc::new(config), "localhost".try_into().unwrap()).unwrap();
+        let mut client = StreamOwned::new(connection, TcpStream::connect(addr).unwrap());
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("secure"));
+
+        // Plain HTTP on the TLS port gets nowhere.
+        let mut plain = TcpStream::connect(addr).unwrap();
+        plain.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        plain.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut response = Vec::new();
+        let _ = plain.read_to_end(&mut response);
+        assert!(!response.starts_with(b"HTTP/1.1"));
+
+        handle.shutdown();
+        running.join().unwrap().unwrap();
+        assert!(Tls::from_pem(b"", b"").is_err());
+    }
+
+    #[test]
+    fn redirects_to_the_same_url_on_https() {
+        let router = redirect_router(8443);
+        let mut raw: &[u8] = b"GET /a%20b?x=1 HTTP/1.1\r\nHost: example.com:8080\r\n\r\n";
+        let response = router.handle(Request::read_from(&mut raw).unwrap());
+        assert_eq!(Status::MovedPermanently, response.status);
+        assert_eq!(
+            Some("https://example.com:8443/a%20b?x=1"),
+            response.headers.get("Location")
+        );
+
+        let router = redirect_router(443);
+        let mut raw: &[u8] = b"POST / HTTP/1.1\r\nHost: [::1]\r\nContent-Length: 0\r\n\r\n";
+        let response = router.handle(Request::read_from(&mut raw).unwrap());
+        assert_eq!(Status::PermanentRedirect, response.status);
+        assert_eq!(Some("https://[::1]/"), response.headers.get("Location"));
+    }
+}
@@ -0,0 +1,49 @@
+use hello::middleware::Middleware;
+use hello::request::Request;
+use hello::response::{Response, Status};
+use hello::router::Router;
+#[cfg(target_os = "linux")]
+use hello::runtime;
+#[cfg(target_os = "linux")]
+use std::time::Duration;
+
+// A small app with a route of every kind, shared by the integration tests.
+pub fn router() -> Router {
+    let mut router = Router::new();
+    router
+        .get("/", |_| Response::new(Status::Ok, "Hello!\n"))
+        .get("/users/:id", |request| {
+            let id = request.param("id").unwrap_or("");
+            Response::new(Status::Ok, format!("user {}\n", id))
+                .with_header("Content-Type", "text/plain; charset=utf-8")
+        })
+        .post("/echo", |request| {
+            Response::new(Status::Created, request.body.clone())
+        })
+        .get("/page", |_| {
+            Response::new(Status::Ok, "<p>Hello, world!</p>\n".repeat(100))
+                .with_header("Content-Type", "text/html; charset=utf-8")
+        });
+    #[cfg(target_os = "linux")]
+    router.get_async("/sleep", |_| async {
+        runtime::sleep(Duration::from_millis(50)).await;
+        Response::new(Status::Ok, "Slept\n")
+    });
+    router
+}
+
+// Turns away requests without the right token.
+pub struct RequireToken(pub &'static str);
+
+impl Middleware for RequireToken {
+    fn before(&self, request: &mut Request) -> Option<Response> {
+        let expected = format!("Bearer {}", self.0);
+        if request.header("Authorization") == Some(expected.as_str()) {
+            return None;
+        }
+        Some(
+            Response::new(Status::Unauthorized, "Unauthorized\n")
+                .with_header("WWW-Authenticate", "Bearer"),
+        )
+    }
+}
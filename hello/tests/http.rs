@@ -0,0 +1,115 @@
+mod common;
+
+use hello::compression::Compression;
+use hello::server::{ConnectionConfig, Server};
+use hello::testing::{TestResponse, TestServer};
+use hello::ThreadPool;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::TcpStream;
+
+#[test]
+fn serves_routes_over_http() {
+    let server = TestServer::start(common::router());
+    let mut client = server.client();
+
+    let response = client.get("/").unwrap();
+    assert_eq!(200, response.status);
+    assert_eq!("OK", response.reason);
+    assert_eq!(
+        Some("text/plain; charset=utf-8"),
+        response.header("Content-Type")
+    );
+    assert_eq!("Hello!\n", response.text());
+
+    assert_eq!("user 42\n", client.get("/users/42").unwrap().text());
+    assert_eq!(404, client.get("/users").unwrap().status);
+
+    let response = client.post("/echo", "ping").unwrap();
+    assert_eq!(201, response.status);
+    assert_eq!("ping", response.text());
+
+    // HEAD gets the GET route's headers, without the body.
+    let response = client.head("/users/42").unwrap();
+    assert_eq!(200, response.status);
+    assert_eq!(Some("8"), response.header("Content-Length"));
+    assert!(response.body.is_empty());
+
+    assert_eq!(501, client.request("BREW", "/").send().unwrap().status);
+    server.stop().unwrap();
+}
+
+#[test]
+fn keeps_connections_open_until_told_otherwise() {
+    let server = TestServer::start_with(|listener| {
+        Server::new(listener, ThreadPool::new(2), common::router()).config(ConnectionConfig {
+            max_requests: 2,
+            ..ConnectionConfig::default()
+        })
+    });
+    let mut client = server.client();
+    let first = client.get("/").unwrap();
+    assert_eq!(Some("keep-alive"), first.header("Connection"));
+    // The second request uses up the connection's budget.
+    let second = client.get("/").unwrap();
+    assert_eq!(Some("close"), second.header("Connection"));
+
+    let response = client
+        .request("GET", "/")
+        .header("Connection", "close")
+        .send()
+        .unwrap();
+    assert_eq!(Some("close"), response.header("Connection"));
+
+    // Pipelined requests come back in order.
+    let mut connection = BufReader::new(TcpStream::connect(server.addr()).unwrap());
+    connection
+        .get_mut()
+        .write_all(b"GET /users/1 HTTP/1.1\r\n\r\nGET /users/2 HTTP/1.1\r\n\r\n")
+        .unwrap();
+    for expected in ["user 1\n", "user 2\n"] {
+        let response = TestResponse::read_from(&mut connection, false).unwrap();
+        assert_eq!(expected, response.text());
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn async_server_serves_the_same_routes() {
+    let server = TestServer::start_async_with(|listener| {
+        Server::new(listener, ThreadPool::new(1), common::router())
+    });
+    let mut client = server.client();
+    assert_eq!("Slept\n", client.get("/sleep").unwrap().text());
+    assert_eq!("user 7\n", client.get("/users/7").unwrap().text());
+    assert_eq!(
+        Some("keep-alive"),
+        client.get("/").unwrap().header("Connection")
+    );
+    server.stop().unwrap();
+}
+
+#[test]
+fn middleware_and_compression_apply_to_every_route() {
+    let mut router = common::router();
+    router.wrap(common::RequireToken("secret"));
+    let server = TestServer::start_with(|listener| {
+        Server::new(listener, ThreadPool::new(2), router).compression(Compression::new())
+    });
+    let mut client = server.client();
+
+    let response = client.get("/page").unwrap();
+    assert_eq!(401, response.status);
+    assert_eq!(Some("Bearer"), response.header("WWW-Authenticate"));
+
+    let response = client
+        .request("GET", "/page")
+        .header("Authorization", "Bearer secret")
+        .header("Accept-Encoding", "gzip")
+        .send()
+        .unwrap();
+    assert_eq!(200, response.status);
+    assert_eq!(Some("gzip"), response.header("Content-Encoding"));
+    assert_eq!(Some("Accept-Encoding"), response.header("Vary"));
+    assert!(response.body.len() < 2100);
+}
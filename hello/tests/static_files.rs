@@ -0,0 +1,73 @@
+use hello::compression::Compression;
+use hello::router::Router;
+use hello::server::Server;
+use hello::static_files::StaticFiles;
+use hello::testing::TestServer;
+use hello::ThreadPool;
+
+fn files() -> Router {
+    let files = StaticFiles::new(env!("CARGO_MANIFEST_DIR"));
+    let mut router = Router::new();
+    router.get("/*path", move |request| {
+        files.serve(request, request.param("path").unwrap_or(""))
+    });
+    router
+}
+
+#[test]
+fn serves_files_with_revalidation() {
+    let server = TestServer::start(files());
+    let mut client = server.client();
+
+    let response = client.get("/hello.html").unwrap();
+    assert_eq!(200, response.status);
+    assert_eq!(
+        Some("text/html; charset=utf-8"),
+        response.header("Content-Type")
+    );
+    assert!(response.text().contains("Hello"));
+    let etag = response.header("ETag").unwrap().to_string();
+
+    let response = client
+        .request("GET", "/hello.html")
+        .header("If-None-Match", &etag)
+        .send()
+        .unwrap();
+    assert_eq!(304, response.status);
+    assert!(response.body.is_empty());
+
+    assert_eq!(404, client.get("/missing.html").unwrap().status);
+    assert_eq!(403, client.get("/../Cargo.toml").unwrap().status);
+}
+
+#[test]
+fn revalidates_compressed_files() {
+    // hello.html is compressed with no minimum size, and too small to be
+    // with the default one. Either way the 304 must agree with the 200.
+    for (min_size, encoding) in [(0, Some("gzip")), (1024, None)] {
+        let server = TestServer::start_with(|listener| {
+            Server::new(listener, ThreadPool::new(2), files())
+                .compression(Compression::new().min_size(min_size))
+        });
+        let mut client = server.client();
+
+        let full = client
+            .request("GET", "/hello.html")
+            .header("Accept-Encoding", "gzip")
+            .send()
+            .unwrap();
+        assert_eq!(encoding, full.header("Content-Encoding"));
+        let etag = full.header("ETag").unwrap();
+        assert_eq!(encoding.is_some(), etag.ends_with("-gzip\""));
+
+        let response = client
+            .request("GET", "/hello.html")
+            .header("Accept-Encoding", "gzip")
+            .header("If-None-Match", etag)
+            .send()
+            .unwrap();
+        assert_eq!(304, response.status);
+        assert_eq!(Some(etag), response.header("ETag"));
+        assert_eq!(full.header("Vary"), response.header("Vary"));
+    }
+}